@@ -0,0 +1,190 @@
+use super::{Debuggee, Event};
+use crate::languages::calculator::interpreter::Numeric;
+use crate::languages::calculator::{interpreter, lexer, parser, CalculatorError};
+use crate::languages::calculator::{Expr, Node, Stmt};
+
+/// Steps through `calculator::interpreter` one `Expr` node at a time.
+#[derive(Default)]
+pub struct Calculator {
+    /// What arithmetic does on overflow
+    pub numeric: Numeric,
+}
+
+impl Debuggee for Calculator {
+    fn id(&self) -> &'static str {
+        "calculator"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["calc"]
+    }
+
+    fn trace(&self, source: &str) -> Result<Vec<Event>, String> {
+        let toks = lexer::lexer(source, 0).map_err(|e| CalculatorError::Lexer(e).to_string())?;
+        let program = parser::program(toks).map_err(|e| CalculatorError::Parser(e).to_string())?;
+        let mut tracer = Tracer {
+            events: Vec::new(),
+            numeric: self.numeric,
+        };
+        for stmt in &program {
+            let (Stmt::Expr(e) | Stmt::Print(e)) = &stmt.node;
+            let Some(value) = tracer.eval(e) else {
+                break;
+            };
+            if let Stmt::Print(_) = stmt.node {
+                tracer.events.push(Event::Output(format!("{}\n", value)));
+            }
+        }
+        Ok(tracer.events)
+    }
+}
+
+struct Tracer {
+    events: Vec<Event>,
+    numeric: Numeric,
+}
+
+impl Tracer {
+    // Evaluates `node`, stopping before it and recording each operand's value
+    // in its frame once known. `None` means evaluation failed, which has been
+    // recorded.
+    fn eval(&mut self, node: &Node) -> Option<i32> {
+        self.events.push(Event::Stop(node.span));
+        let value = match &node.node {
+            Expr::Add((e1, e2))
+            | Expr::Sub((e1, e2))
+            | Expr::Mult((e1, e2))
+            | Expr::Div((e1, e2)) => {
+                let mut values = [0; 2];
+                for ((name, operand), value) in
+                    [("left", e1), ("right", e2)].into_iter().zip(&mut values)
+                {
+                    *value = self.eval(operand)?;
+                    self.events
+                        .push(Event::Local(String::from(name), value.to_string()));
+                }
+                // Only the last step is left, with the operands known
+                interpreter::operate(node, values[0], values[1], self.numeric)
+            }
+            // A leaf, so cloning it is cheap
+            _ => interpreter::interpreter_with(node.clone(), self.numeric),
+        };
+        match value {
+            Ok(value) => {
+                self.events.push(Event::Return);
+                Some(value)
+            }
+            Err(e) => {
+                let message = CalculatorError::Interpreter(e).to_string();
+                self.events.push(Event::Error(message));
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::frames;
+    use super::*;
+
+    // Each stop, error and output as the innermost frame's name and every
+    // frame's locals
+    fn summary(source: &str, events: &[Event]) -> Vec<String> {
+        events
+            .iter()
+            .enumerate()
+            .filter_map(|(i, event)| match event {
+                Event::Stop(_) | Event::Error(_) => {
+                    let frames = frames(source, &events[..=i]);
+                    let frame = frames.last().unwrap();
+                    let locals: Vec<String> = frames
+                        .iter()
+                        .flat_map(|frame| &frame.locals)
+                        .map(|(name, value)| format!("{}={}", name, value))
+                        .collect();
+                    Some(format!(
+                        "{} {} [{}]",
+                        frames.len(),
+                        frame.name,
+                        locals.join(" ")
+                    ))
+                }
+                Event::Output(output) => Some(format!("out {:?}", output)),
+                Event::Local(..) | Event::Return => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn steps_per_node() {
+        let source = "print + 1 * 2 3";
+        let events = Calculator::default().trace(source).unwrap();
+        assert_eq!(
+            summary(source, &events),
+            vec![
+                "1 + 1 * 2 3 []",
+                "2 1 []",
+                "2 * 2 3 [left=1]",
+                "3 2 [left=1]",
+                "3 3 [left=1 left=2]",
+                "out \"7\\n\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_at_failure() {
+        let source = "/ 1 0\nprint 2";
+        let events = Calculator::default().trace(source).unwrap();
+        assert_eq!(
+            events.last(),
+            Some(&Event::Error(String::from(
+                "Interpreter error [I001]: division by zero"
+            )))
+        );
+        assert_eq!(frames(source, &events).len(), 1);
+        assert_eq!(
+            Calculator::default().trace("+ 1").unwrap_err(),
+            "Parser error [P003]: unexpected end of input"
+        );
+    }
+
+    #[test]
+    fn overflow_mode() {
+        let program = "print + 1 * 65536 32768";
+        let events = Calculator::default().trace(program).unwrap();
+        assert_eq!(
+            events.last(),
+            Some(&Event::Error(String::from(
+                "Interpreter error [I005]: arithmetic overflow"
+            )))
+        );
+        assert_eq!(frames(program, &events).len(), 2);
+        let wrapping = Calculator {
+            numeric: Numeric::Wrapping,
+        };
+        assert_eq!(
+            wrapping.trace(program).unwrap().last(),
+            Some(&Event::Output(format!("{}\n", i32::MIN + 1)))
+        );
+    }
+
+    #[test]
+    fn deep_programs() {
+        // Every operator is the left operand of the one before, so the stack
+        // gets as deep as the program is long
+        let depth = 500;
+        let source = format!("{}{}", "+ ".repeat(depth), "1 ".repeat(depth + 1));
+        let events = Calculator::default().trace(&source).unwrap();
+        // A stop, two locals and a return per operator, a stop and a return
+        // per number
+        assert_eq!(events.len(), 4 * depth + 2 * (depth + 1));
+        let stops = events
+            .iter()
+            .position(|event| *event == Event::Return)
+            .unwrap();
+        assert_eq!(frames(&source, &events[..stops]).len(), depth + 1);
+        assert!(frames(&source, &events).is_empty());
+    }
+}
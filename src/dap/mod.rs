@@ -0,0 +1,666 @@
+use crate::framing;
+use crate::languages::json::Value;
+use crate::languages::span::Span;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+mod calculator;
+
+pub use calculator::Calculator;
+
+/// An interpreter the debugger can step through.
+pub trait Debuggee {
+    /// The `language` a launch configuration can name it by
+    fn id(&self) -> &'static str;
+
+    /// Extensions of its programs, without the dot
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Runs `source` to the end, or to its first error, recording every point
+    /// it could stop at. Fails if the program cannot run at all, e.g. because
+    /// it does not parse.
+    fn trace(&self, source: &str) -> Result<Vec<Event>, String>;
+}
+
+/// Something that happened while running a program. Each `Stop` opens a frame
+/// inside those still open and `Return` closes it, so the stack at any event
+/// is rebuilt from the ones before it rather than kept with every event.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Event {
+    /// About to evaluate the node at this span, in a new innermost frame
+    Stop(Span),
+    /// The innermost frame learned a value, e.g. of one of its operands
+    Local(String, String),
+    /// The innermost frame finished
+    Return,
+    /// Text the program wrote
+    Output(String),
+    /// Evaluating the innermost frame failed, ending the program
+    Error(String),
+}
+
+/// A node under evaluation, named by its source text, with what is known so far
+/// of its operands.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Frame {
+    pub name: String,
+    pub span: Span,
+    pub locals: Vec<(String, String)>,
+}
+
+/// The frames open once `events` have happened, outermost first.
+fn frames(source: &str, events: &[Event]) -> Vec<Frame> {
+    let mut frames: Vec<Frame> = Vec::new();
+    for event in events {
+        match event {
+            Event::Stop(span) => frames.push(Frame {
+                name: source
+                    .get(span.start..span.end)
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                span: *span,
+                locals: Vec::new(),
+            }),
+            Event::Local(name, value) => {
+                if let Some(frame) = frames.last_mut() {
+                    frame.locals.push((name.clone(), value.clone()));
+                }
+            }
+            Event::Return => {
+                frames.pop();
+            }
+            Event::Output(_) | Event::Error(_) => {}
+        }
+    }
+    frames
+}
+
+/// Every interpreter the crate implements.
+pub fn debuggees() -> Vec<Box<dyn Debuggee>> {
+    vec![Box::new(Calculator::default())]
+}
+
+/// Speaks the Debug Adapter Protocol over `input` and `output` until the client
+/// disconnects or input ends.
+pub fn serve(
+    input: impl BufRead,
+    output: impl Write,
+    debuggees: Vec<Box<dyn Debuggee>>,
+) -> io::Result<()> {
+    let mut session = Session {
+        input,
+        output,
+        seq: 0,
+        debuggees,
+        program: None,
+        breakpoints: Vec::new(),
+    };
+    session.run()
+}
+
+// The program being debugged
+struct Program {
+    path: String,
+    source: String,
+    events: Vec<Event>,
+    /// The event execution is stopped at, once started
+    at: Option<usize>,
+    stop_on_entry: bool,
+    no_debug: bool,
+}
+
+// How far to run before stopping again
+#[derive(PartialEq, Clone, Copy)]
+enum Run {
+    /// To the first stop, or the first breakpoint
+    Start,
+    Continue,
+    /// To the next node, however deep
+    In,
+    /// To the next node no deeper than this one
+    Over,
+    /// To the next node shallower than this one
+    Out,
+}
+
+// Spans count columns in bytes, but clients count them in UTF-16 code units,
+// from 1
+fn column(source: &str, span: Span) -> usize {
+    let start = span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    source[line_start..start].encode_utf16().count() + 1
+}
+
+// How many frames are open after `event`, given how many were before it
+fn next_depth(depth: usize, event: &Event) -> usize {
+    match event {
+        Event::Stop(_) => depth + 1,
+        Event::Return => depth.saturating_sub(1),
+        _ => depth,
+    }
+}
+
+// The only thread
+const THREAD: usize = 1;
+
+struct Session<R, W> {
+    input: R,
+    output: W,
+    /// Sequence number of the last message sent
+    seq: usize,
+    debuggees: Vec<Box<dyn Debuggee>>,
+    program: Option<Program>,
+    /// Lines to stop at when a statement starts on them
+    breakpoints: Vec<usize>,
+}
+
+impl<R: BufRead, W: Write> Session<R, W> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(body) = framing::receive(&mut self.input)? {
+            let Ok(request) = Value::parse(&body) else {
+                continue;
+            };
+            if request.get("type").and_then(Value::as_str) != Some("request") {
+                continue;
+            }
+            let seq = request.get("seq").cloned().unwrap_or(Value::Null);
+            let command = request
+                .get("command")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            let arguments = request.get("arguments").cloned().unwrap_or(Value::Null);
+
+            let result = self.request(&command, &arguments);
+            let (success, body, message) = match result {
+                Ok(body) => (true, body, None),
+                Err(message) => (false, Value::Null, Some(message)),
+            };
+            self.send(
+                "response",
+                vec![
+                    ("request_seq", seq),
+                    ("success", Value::from(success)),
+                    ("command", Value::from(command.as_str())),
+                    ("message", Value::from(message)),
+                    ("body", body),
+                ],
+            )?;
+
+            // Whatever a request sets going happens after it is answered
+            match command.as_str() {
+                "initialize" => self.event("initialized", Value::Null)?,
+                "configurationDone" => self.advance(Run::Start)?,
+                "continue" => self.advance(Run::Continue)?,
+                "stepIn" => self.advance(Run::In)?,
+                "next" => self.advance(Run::Over)?,
+                "stepOut" => self.advance(Run::Out)?,
+                "disconnect" | "terminate" => return Ok(()),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn request(&mut self, command: &str, arguments: &Value) -> Result<Value, String> {
+        match command {
+            "initialize" => Ok(Value::object(vec![(
+                "supportsConfigurationDoneRequest",
+                Value::from(true),
+            )])),
+            "launch" => {
+                let path = arguments
+                    .get("program")
+                    .and_then(Value::as_str)
+                    .ok_or("launch needs a `program`")?;
+                let language = arguments.get("language").and_then(Value::as_str);
+                let extension = path.rsplit_once('.').map(|(_, extension)| extension);
+                let debuggee = self
+                    .debuggees
+                    .iter()
+                    .find(|debuggee| match language {
+                        Some(language) => debuggee.id() == language,
+                        None => extension.is_some_and(|e| debuggee.extensions().contains(&e)),
+                    })
+                    .ok_or_else(|| format!("no interpreter for `{}`", path))?;
+                let source = fs::read_to_string(path)
+                    .map_err(|e| format!("could not read `{}`: {}", path, e))?;
+                let flag = |name| arguments.get(name) == Some(&Value::Bool(true));
+                self.program = Some(Program {
+                    path: path.to_string(),
+                    events: debuggee.trace(&source)?,
+                    source,
+                    at: None,
+                    stop_on_entry: flag("stopOnEntry"),
+                    no_debug: flag("noDebug"),
+                });
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => {
+                let requested = arguments
+                    .get("breakpoints")
+                    .and_then(Value::as_array)
+                    .unwrap_or_default();
+                self.breakpoints = requested
+                    .iter()
+                    .filter_map(|breakpoint| breakpoint.get("line").and_then(Value::as_f64))
+                    .map(|line| line as usize)
+                    .collect();
+                // Only lines a statement starts on can be stopped at
+                let mut depth = 0;
+                let statements: Vec<usize> = self
+                    .program
+                    .iter()
+                    .flat_map(|program| &program.events)
+                    .filter_map(|event| {
+                        let line = match event {
+                            Event::Stop(span) if depth == 0 => Some(span.line),
+                            _ => None,
+                        };
+                        depth = next_depth(depth, event);
+                        line
+                    })
+                    .collect();
+                let breakpoints: Vec<Value> = self
+                    .breakpoints
+                    .iter()
+                    .map(|line| {
+                        Value::object(vec![
+                            ("verified", Value::from(statements.contains(line))),
+                            ("line", Value::from(*line)),
+                        ])
+                    })
+                    .collect();
+                Ok(Value::object(vec![(
+                    "breakpoints",
+                    Value::Array(breakpoints),
+                )]))
+            }
+            "configurationDone" | "continue" | "stepIn" | "next" | "stepOut" | "pause"
+            | "disconnect" | "terminate" => Ok(Value::Null),
+            "threads" => Ok(Value::object(vec![(
+                "threads",
+                Value::Array(vec![Value::object(vec![
+                    ("id", Value::from(THREAD)),
+                    ("name", Value::from("main")),
+                ])]),
+            )])),
+            "stackTrace" => {
+                let (program, frames) = self.stopped()?;
+                let source = Value::object(vec![("path", Value::from(program.path.as_str()))]);
+                // Innermost first, numbered from 1
+                let frames: Vec<Value> = frames
+                    .iter()
+                    .rev()
+                    .enumerate()
+                    .map(|(i, frame)| {
+                        Value::object(vec![
+                            ("id", Value::from(i + 1)),
+                            ("name", Value::from(frame.name.as_str())),
+                            ("source", source.clone()),
+                            ("line", Value::from(frame.span.line)),
+                            ("column", Value::from(column(&program.source, frame.span))),
+                        ])
+                    })
+                    .collect();
+                Ok(Value::object(vec![
+                    ("totalFrames", Value::from(frames.len())),
+                    ("stackFrames", Value::Array(frames)),
+                ]))
+            }
+            // A frame's locals are its only scope, so share its id
+            "scopes" => {
+                let id = arguments.get("frameId").cloned().unwrap_or(Value::Null);
+                Ok(Value::object(vec![(
+                    "scopes",
+                    Value::Array(vec![Value::object(vec![
+                        ("name", Value::from("Locals")),
+                        ("variablesReference", id),
+                        ("expensive", Value::from(false)),
+                    ])]),
+                )]))
+            }
+            "variables" => {
+                let (_, frames) = self.stopped()?;
+                let reference = arguments
+                    .get("variablesReference")
+                    .and_then(Value::as_f64)
+                    .unwrap_or_default() as usize;
+                let frame = frames
+                    .len()
+                    .checked_sub(reference)
+                    .and_then(|i| frames.get(i))
+                    .ok_or("no such frame")?;
+                let variables: Vec<Value> = frame
+                    .locals
+                    .iter()
+                    .map(|(name, value)| {
+                        Value::object(vec![
+                            ("name", Value::from(name.as_str())),
+                            ("value", Value::from(value.as_str())),
+                            ("variablesReference", Value::from(0)),
+                        ])
+                    })
+                    .collect();
+                Ok(Value::object(vec![("variables", Value::Array(variables))]))
+            }
+            _ => Err(format!("`{}` is not supported", command)),
+        }
+    }
+
+    // The program and the frames it is stopped in
+    fn stopped(&self) -> Result<(&Program, Vec<Frame>), String> {
+        let program = self.program.as_ref().ok_or("nothing has been launched")?;
+        match program.at {
+            Some(at) => Ok((program, frames(&program.source, &program.events[..=at]))),
+            None => Err(String::from("the program is not stopped")),
+        }
+    }
+
+    // Runs until the next place `run` stops at, or the end of the program
+    fn advance(&mut self, run: Run) -> io::Result<()> {
+        let Some(program) = &mut self.program else {
+            return Ok(());
+        };
+        // How many frames are open just after the stop, and so just before
+        // the events still to come
+        let open = |at: usize| program.events[..=at].iter().fold(0, next_depth);
+        let (from, depth, failed) = match program.at.map(|at| (at, &program.events[at])) {
+            Some((at, Event::Stop(_))) => (at + 1, open(at), false),
+            Some((_, Event::Error(_))) => (program.events.len(), 0, true),
+            _ => (0, 0, false),
+        };
+        let stop_on_entry = program.stop_on_entry;
+        let no_debug = program.no_debug;
+        let events: Vec<(usize, Event)> = program.events[from..]
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(i, event)| (from + i, event))
+            .collect();
+
+        let mut open = depth;
+        for (at, event) in events {
+            open = next_depth(open, &event);
+            let reason = match &event {
+                Event::Output(output) => {
+                    self.event(
+                        "output",
+                        Value::object(vec![
+                            ("category", Value::from("stdout")),
+                            ("output", Value::from(output.as_str())),
+                        ]),
+                    )?;
+                    continue;
+                }
+                Event::Local(..) | Event::Return => continue,
+                Event::Error(_) if no_debug => break,
+                Event::Error(_) => "exception",
+                Event::Stop(_) if no_debug => continue,
+                Event::Stop(span) => match run {
+                    Run::Start if stop_on_entry => "entry",
+                    Run::In => "step",
+                    Run::Over if open <= depth => "step",
+                    Run::Out if open < depth => "step",
+                    Run::Start | Run::Continue
+                        if open == 1 && self.breakpoints.contains(&span.line) =>
+                    {
+                        "breakpoint"
+                    }
+                    _ => continue,
+                },
+            };
+            let text = match &event {
+                Event::Error(message) => Value::from(message.as_str()),
+                _ => Value::Null,
+            };
+            if let Some(program) = &mut self.program {
+                program.at = Some(at);
+            }
+            return self.event(
+                "stopped",
+                Value::object(vec![
+                    ("reason", Value::from(reason)),
+                    ("threadId", Value::from(THREAD)),
+                    ("allThreadsStopped", Value::from(true)),
+                    ("text", text),
+                ]),
+            );
+        }
+
+        let failed = failed
+            || self
+                .program
+                .as_ref()
+                .is_some_and(|program| matches!(program.events.last(), Some(Event::Error(_))));
+        self.program = None;
+        self.event(
+            "exited",
+            Value::object(vec![("exitCode", Value::from(i32::from(failed)))]),
+        )?;
+        self.event("terminated", Value::Null)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send("event", vec![("event", Value::from(event)), ("body", body)])
+    }
+
+    fn send(&mut self, kind: &str, fields: Vec<(&str, Value)>) -> io::Result<()> {
+        self.seq += 1;
+        let mut message = vec![("seq", Value::from(self.seq)), ("type", Value::from(kind))];
+        message.extend(
+            fields
+                .into_iter()
+                .filter(|(_, value)| *value != Value::Null),
+        );
+        framing::send(&mut self.output, &Value::object(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A client that sends `requests` in order without waiting, as the server
+    // handles one at a time, and returns everything sent back
+    fn script(requests: &[(&str, &str)]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for (seq, (command, arguments)) in requests.iter().enumerate() {
+            let body = format!(
+                r#"{{"seq":{},"type":"request","command":"{}","arguments":{}}}"#,
+                seq + 1,
+                command,
+                arguments
+            );
+            write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(&input[..], &mut output, debuggees()).unwrap();
+        let mut messages = Vec::new();
+        let mut rest = &output[..];
+        while let Some(body) = framing::receive(&mut rest).unwrap() {
+            messages.push(Value::parse(&body).unwrap());
+        }
+        messages
+    }
+
+    fn program(name: &str, source: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("etop-dap-{}-{}.calc", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    // A message by what it is: a response's command or an event's name
+    fn label(message: &Value) -> String {
+        let field = |name| {
+            message
+                .get(name)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+        };
+        match field("type") {
+            "response" if message.get("success") == Some(&Value::Bool(false)) => {
+                format!("failed {}", field("command"))
+            }
+            "response" => field("command").to_string(),
+            _ => match message.get("body").and_then(|body| body.get("reason")) {
+                Some(reason) => format!("{} {}", field("event"), reason.as_str().unwrap()),
+                None => format!("event {}", field("event")),
+            },
+        }
+    }
+
+    fn body<'a>(messages: &'a [Value], command: &str, nth: usize) -> &'a Value {
+        messages
+            .iter()
+            .filter(|message| message.get("command").and_then(Value::as_str) == Some(command))
+            .nth(nth)
+            .and_then(|message| message.get("body"))
+            .unwrap()
+    }
+
+    #[test]
+    fn breakpoint_and_steps() {
+        let path = program("steps", "print + 1 2\n* 3 / 8 4\nprint 5\n");
+        let launch = format!(r#"{{"program":"{}"}}"#, path);
+        let breakpoints = format!(
+            r#"{{"source":{{"path":"{}"}},"breakpoints":[{{"line":2}},{{"line":9}}]}}"#,
+            path
+        );
+        let messages = script(&[
+            ("initialize", r#"{"adapterID":"languages"}"#),
+            ("launch", &launch),
+            ("setBreakpoints", &breakpoints),
+            ("configurationDone", "{}"),
+            ("stepIn", r#"{"threadId":1}"#),
+            ("stepIn", r#"{"threadId":1}"#),
+            ("stepIn", r#"{"threadId":1}"#),
+            ("stackTrace", r#"{"threadId":1}"#),
+            ("variables", r#"{"variablesReference":3}"#),
+            ("stepOut", r#"{"threadId":1}"#),
+            ("continue", r#"{"threadId":1}"#),
+            ("disconnect", "{}"),
+        ]);
+        let _ = fs::remove_file(&path);
+
+        let labels: Vec<String> = messages.iter().map(label).collect();
+        assert_eq!(
+            labels,
+            vec![
+                "initialize",
+                "event initialized",
+                "launch",
+                "setBreakpoints",
+                "configurationDone",
+                "event output",
+                "stopped breakpoint",
+                "stepIn",
+                "stopped step",
+                "stepIn",
+                "stopped step",
+                "stepIn",
+                "stopped step",
+                "stackTrace",
+                "variables",
+                "stepOut",
+                "stopped step",
+                "continue",
+                "event output",
+                "event exited",
+                "event terminated",
+                "disconnect",
+            ]
+        );
+        assert_eq!(
+            body(&messages, "setBreakpoints", 0).to_string(),
+            r#"{"breakpoints":[{"verified":true,"line":2},{"verified":false,"line":9}]}"#
+        );
+        // Stopped at `8` in `/ 8 4`, with `3` already worked out
+        let frames = body(&messages, "stackTrace", 0).get("stackFrames").unwrap();
+        let names: Vec<&str> = frames
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|frame| frame.get("name").and_then(Value::as_str).unwrap())
+            .collect();
+        assert_eq!(names, vec!["8", "/ 8 4", "* 3 / 8 4"]);
+        assert_eq!(
+            body(&messages, "variables", 0).to_string(),
+            r#"{"variables":[{"name":"left","value":"3","variablesReference":0}]}"#
+        );
+        assert_eq!(
+            messages[5].get("body").unwrap().get("output"),
+            Some(&Value::from("3\n"))
+        );
+    }
+
+    #[test]
+    fn locals_and_exceptions() {
+        let path = program("locals", "- 9 / 8 0");
+        let launch = format!(r#"{{"program":"{}","stopOnEntry":true}}"#, path);
+        let messages = script(&[
+            ("initialize", "{}"),
+            ("launch", &launch),
+            ("configurationDone", "{}"),
+            ("stepIn", r#"{"threadId":1}"#),
+            ("next", r#"{"threadId":1}"#),
+            ("variables", r#"{"variablesReference":2}"#),
+            ("continue", r#"{"threadId":1}"#),
+            ("continue", r#"{"threadId":1}"#),
+        ]);
+        let _ = fs::remove_file(&path);
+
+        let labels: Vec<String> = messages.iter().map(label).collect();
+        assert_eq!(
+            labels,
+            vec![
+                "initialize",
+                "event initialized",
+                "launch",
+                "configurationDone",
+                "stopped entry",
+                "stepIn",
+                "stopped step",
+                "next",
+                "stopped step",
+                "variables",
+                "continue",
+                "stopped exception",
+                "continue",
+                "event exited",
+                "event terminated",
+            ]
+        );
+        assert_eq!(
+            body(&messages, "variables", 0).to_string(),
+            r#"{"variables":[{"name":"left","value":"9","variablesReference":0}]}"#
+        );
+        assert_eq!(
+            messages[11].get("body").unwrap().get("text"),
+            Some(&Value::from("Interpreter error [I001]: division by zero"))
+        );
+        assert_eq!(
+            messages[13].get("body").unwrap().to_string(),
+            r#"{"exitCode":1}"#
+        );
+    }
+
+    #[test]
+    fn launch_failures() {
+        let path = program("broken", "+ 1");
+        let launch = format!(r#"{{"program":"{}"}}"#, path);
+        let messages = script(&[("launch", &launch), ("launch", r#"{"program":"a.txt"}"#)]);
+        let _ = fs::remove_file(&path);
+        assert_eq!(
+            messages[0].get("message"),
+            Some(&Value::from("Parser error [P003]: unexpected end of input"))
+        );
+        assert_eq!(
+            messages[1].get("message"),
+            Some(&Value::from("no interpreter for `a.txt`"))
+        );
+    }
+}
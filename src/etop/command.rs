@@ -0,0 +1,118 @@
+/// A colon-prefixed etop meta-command, e.g. `:tokens + 1 2`.
+#[derive(PartialEq, Debug)]
+pub enum Command {
+    Tokens(String),
+    Ast(String),
+    Time(String),
+    Trace(String),
+    Lang(String),
+    Langs,
+    Load(String),
+    Save(String),
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// The calculator source a command works on, if it takes any.
+    pub fn source(&self) -> Option<&str> {
+        match self {
+            Command::Tokens(src) | Command::Ast(src) | Command::Time(src) | Command::Trace(src) => {
+                Some(src)
+            }
+            Command::Lang(_)
+            | Command::Langs
+            | Command::Load(_)
+            | Command::Save(_)
+            | Command::Help
+            | Command::Quit => None,
+        }
+    }
+}
+
+/// Every command as typed, for completion.
+pub const NAMES: [&str; 11] = [
+    ":tokens", ":ast", ":time", ":trace", ":lang", ":langs", ":load", ":save", ":help", ":quit",
+    ":q",
+];
+
+pub const HELP: &str = "\
+:tokens <src>  print the token stream the lexer produces
+:ast <src>     print the parsed syntax tree
+:time <src>    evaluate, reporting lex, parse and eval durations
+:trace <src>   show each step of evaluating, one reduction at a time
+:lang <name>   switch language, keeping each language's session
+:langs         list the available languages
+:load <file>   run a program file in this session
+:save <file>   write this session's successful inputs to a replayable file
+:help          show this message
+:quit          leave etop";
+
+/// Parses a line of input as a meta-command. `None` means the line is not a
+/// command at all and should be evaluated.
+pub fn parse(input: &str) -> Option<Result<Command, String>> {
+    let rest = input.strip_prefix(':')?;
+    let (name, arg) = match rest.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim().to_string()),
+        None => (rest, String::new()),
+    };
+    let command = match name {
+        "tokens" => Command::Tokens(arg),
+        "ast" => Command::Ast(arg),
+        "time" => Command::Time(arg),
+        "trace" => Command::Trace(arg),
+        "lang" if !arg.is_empty() => Command::Lang(arg),
+        "lang" => return Some(Err(String::from("usage: :lang <name>; try `:langs`"))),
+        "langs" => Command::Langs,
+        "load" if !arg.is_empty() => Command::Load(arg),
+        "save" if !arg.is_empty() => Command::Save(arg),
+        "load" | "save" => return Some(Err(format!("usage: :{} <file>", name))),
+        "help" => Command::Help,
+        "quit" | "q" => Command::Quit,
+        _ => return Some(Err(format!("unknown command `:{}`; try `:help`", name))),
+    };
+    Some(Ok(command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_a_command() {
+        assert_eq!(parse("+ 1 2"), None);
+    }
+
+    #[test]
+    fn with_argument() {
+        assert_eq!(
+            parse(":tokens  + 1 2 "),
+            Some(Ok(Command::Tokens(String::from("+ 1 2"))))
+        );
+        assert_eq!(parse(":quit"), Some(Ok(Command::Quit)));
+    }
+
+    #[test]
+    fn lang() {
+        assert_eq!(
+            parse(":lang calculator"),
+            Some(Ok(Command::Lang(String::from("calculator"))))
+        );
+        assert!(parse(":lang").unwrap().is_err());
+        assert_eq!(parse(":langs"), Some(Ok(Command::Langs)));
+    }
+
+    #[test]
+    fn files() {
+        assert_eq!(
+            parse(":load work.calc"),
+            Some(Ok(Command::Load(String::from("work.calc"))))
+        );
+        assert!(parse(":save").unwrap().is_err());
+    }
+
+    #[test]
+    fn unknown() {
+        assert!(parse(":frobnicate 1").unwrap().is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use super::{find_language, Options};
+use crate::languages::calculator::interpreter::Numeric;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Settings read from `config.toml` in the config directory, e.g.
+///
+/// ```toml
+/// language = "calculator"   # skip the menu
+/// prompt = "calc> "
+/// result_prefix = "= "
+/// color = false
+/// numeric = "wrapping"      # or "checked", "saturating"
+///
+/// [startup]                 # scripts run when a language is first used,
+/// calculator = "init.calc"  # relative to the config directory
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// The language to start in when none is named
+    pub language: Option<&'static str>,
+    /// Whether to colour input; unset leaves it to `NO_COLOR`
+    pub color: Option<bool>,
+    pub options: Options,
+}
+
+impl Config {
+    /// Reads the config file, if there is one.
+    pub fn load() -> Result<Config, String> {
+        let Some(dir) = super::config_dir() else {
+            return Ok(Config::default());
+        };
+        let path = dir.join("config.toml");
+        match fs::read_to_string(&path) {
+            Ok(text) => Config::parse(&text, &dir).map_err(|e| format!("{}:{}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!("could not read `{}`: {}", path.display(), e)),
+        }
+    }
+
+    /// Parses the text of a config file, resolving relative paths against
+    /// `dir`. Errors start with the line they were found on.
+    pub fn parse(text: &str, dir: &Path) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut table = String::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let at = |message: String| format!("{}: {}", number + 1, message);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                let (name, rest) = name
+                    .split_once(']')
+                    .ok_or_else(|| at(String::from("expected `]`")))?;
+                end_of_line(rest).map_err(at)?;
+                table = name.trim().to_string();
+                if table != "startup" {
+                    return Err(at(format!("unknown table `{}`", table)));
+                }
+                continue;
+            }
+
+            let (key, rest) = line
+                .split_once('=')
+                .ok_or_else(|| at(String::from("expected `key = value`")))?;
+            let key = key.trim();
+            let value = value(rest.trim()).map_err(at)?;
+            match (table.as_str(), key, value) {
+                ("", "language", Value::String(name)) => {
+                    config.language = Some(find_language(&name).map_err(at)?)
+                }
+                ("", "prompt", Value::String(prompt)) => config.options.prompt = prompt,
+                ("", "result_prefix", Value::String(prefix)) => {
+                    config.options.result_prefix = prefix
+                }
+                ("", "color", Value::Bool(color)) => config.color = Some(color),
+                ("", "numeric", Value::String(mode)) => {
+                    config.options.numeric = match mode.as_str() {
+                        "checked" => Numeric::Checked,
+                        "wrapping" => Numeric::Wrapping,
+                        "saturating" => Numeric::Saturating,
+                        _ => {
+                            return Err(at(format!(
+                                "unknown numeric mode `{}`; expected one of: checked, \
+                                 wrapping, saturating",
+                                mode
+                            )))
+                        }
+                    }
+                }
+                ("startup", language, Value::String(path)) => {
+                    let language = find_language(language).map_err(at)?;
+                    config.options.startup.insert(language, dir.join(path));
+                }
+                ("", "language" | "prompt" | "result_prefix" | "numeric", _) => {
+                    return Err(at(format!("`{}` should be a string", key)))
+                }
+                ("", "color", _) => {
+                    return Err(at(String::from("`color` should be true or false")))
+                }
+                ("startup", _, _) => return Err(at(String::from("a script should be a path"))),
+                _ => return Err(at(format!("unknown setting `{}`", key))),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// A value on the right of `=`.
+#[derive(PartialEq, Debug)]
+enum Value {
+    String(String),
+    Bool(bool),
+}
+
+// Parses a value and checks nothing but a comment follows it
+fn value(text: &str) -> Result<Value, String> {
+    if let Some(rest) = text.strip_prefix("true") {
+        end_of_line(rest)?;
+        return Ok(Value::Bool(true));
+    }
+    if let Some(rest) = text.strip_prefix("false") {
+        end_of_line(rest)?;
+        return Ok(Value::Bool(false));
+    }
+    let Some(quoted) = text.strip_prefix('"') else {
+        return Err(String::from("expected a string, `true` or `false`"));
+    };
+
+    let mut string = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                end_of_line(&quoted[i + 1..])?;
+                return Ok(Value::String(string));
+            }
+            '\\' => match chars.next() {
+                Some((_, 'n')) => string.push('\n'),
+                Some((_, 't')) => string.push('\t'),
+                Some((_, '"')) => string.push('"'),
+                Some((_, '\\')) => string.push('\\'),
+                _ => return Err(String::from("unknown escape in string")),
+            },
+            c => string.push(c),
+        }
+    }
+    Err(String::from("unterminated string"))
+}
+
+fn end_of_line(rest: &str) -> Result<(), String> {
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected `{}`", rest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn settings() {
+        let config = Config::parse(
+            "# etop settings\n\
+             language = \"calculator\"\n\
+             prompt = \"calc> \" # with a space\n\
+             result_prefix = \"= \"\n\
+             color = false\n\
+             numeric = \"saturating\"\n\
+             \n\
+             [startup]\n\
+             Calculator = \"init.calc\"\n",
+            Path::new("/etc/etop"),
+        )
+        .unwrap();
+        assert_eq!(config.language, Some("Calculator"));
+        assert_eq!(config.color, Some(false));
+        assert_eq!(config.options.prompt, "calc> ");
+        assert_eq!(config.options.result_prefix, "= ");
+        assert_eq!(config.options.numeric, Numeric::Saturating);
+        assert_eq!(
+            config.options.startup.get("Calculator"),
+            Some(&PathBuf::from("/etc/etop/init.calc"))
+        );
+    }
+
+    #[test]
+    fn empty_is_default() {
+        let config = Config::parse("", Path::new(".")).unwrap();
+        assert_eq!(config.language, None);
+        assert_eq!(config.options.prompt, Options::default().prompt);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            value(r#""a \"b\"\\n" # done"#),
+            Ok(Value::String(String::from("a \"b\"\\n")))
+        );
+        assert_eq!(value("\"open"), Err(String::from("unterminated string")));
+        assert_eq!(value("\"x\" y"), Err(String::from("unexpected `y`")));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let parse = |text| Config::parse(text, Path::new(".")).unwrap_err();
+        assert_eq!(parse("\ncolour = true"), "2: unknown setting `colour`");
+        assert_eq!(
+            parse("color = \"yes\""),
+            "1: `color` should be true or false"
+        );
+        assert_eq!(
+            parse("numeric = \"float\""),
+            "1: unknown numeric mode `float`; expected one of: checked, wrapping, saturating"
+        );
+        assert_eq!(
+            parse("[startup]\nlisp = \"init.lisp\""),
+            "2: unknown language `lisp`; expected one of: Calculator"
+        );
+        assert_eq!(parse("[bindings]"), "1: unknown table `bindings`");
+    }
+}
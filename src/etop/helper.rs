@@ -0,0 +1,288 @@
+use super::command;
+use crate::languages::calculator::{lexer, Tok};
+use console::Style;
+use std::fs;
+use std::ops::Range;
+
+/// Language-specific help for the line editor.
+pub trait Helper {
+    /// `line` with colours applied. The visible text must not change.
+    fn highlight(&self, line: &str) -> String;
+
+    /// Whole-word candidates for the word ending the text before the cursor.
+    fn complete(&self, before: &str) -> Vec<String>;
+}
+
+/// How a stretch of calculator input is coloured.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Class {
+    Operator,
+    Number,
+    Keyword,
+    Separator,
+    Comment,
+    Error,
+    /// Whitespace and anything else left as typed
+    Plain,
+}
+
+impl Class {
+    fn style(self) -> Style {
+        match self {
+            Class::Operator => Style::new().yellow(),
+            Class::Number => Style::new().cyan(),
+            Class::Keyword => Style::new().blue().bold(),
+            Class::Separator | Class::Plain => Style::new(),
+            Class::Comment => Style::new().dim(),
+            Class::Error => Style::new().red().underlined(),
+        }
+    }
+}
+
+pub struct CalculatorHelper;
+
+const CALCULATOR_BUILTINS: [&str; 1] = ["print"];
+
+/// Splits calculator input into classified byte ranges covering all of it. Input
+/// the lexer rejects is marked as an error and lexing resumes just after it.
+pub fn classify(src: &str) -> Vec<(Class, Range<usize>)> {
+    let mut classes = Vec::new();
+    let mut pos = 0;
+    while pos < src.len() {
+        // Lex up to the next error, if any
+        let (toks, error) = lexer::lexer_partial(src, pos);
+        let (end, error) = match error.and_then(|e| e.span()) {
+            Some(span) => (span.start, Some(span.start..span.end.max(span.start + 1))),
+            None => (src.len(), None),
+        };
+        for tok in toks {
+            gap(src, pos..tok.span.start, &mut classes);
+            let class = match tok.node {
+                Tok::TokAdd | Tok::TokSub | Tok::TokMult | Tok::TokDiv => Class::Operator,
+                Tok::TokInt(_) => Class::Number,
+                Tok::TokPrint => Class::Keyword,
+                Tok::TokSemi | Tok::TokNewline => Class::Separator,
+            };
+            classes.push((class, tok.span.start..tok.span.end));
+            pos = tok.span.end;
+        }
+        gap(src, pos..end, &mut classes);
+        pos = end;
+        if let Some(error) = error {
+            pos = error.end.min(src.len());
+            // Runs of rejected characters read better as one error
+            match classes.last_mut() {
+                Some((Class::Error, range)) if range.end == error.start => range.end = pos,
+                _ => classes.push((Class::Error, error.start..pos)),
+            }
+        }
+    }
+    classes
+}
+
+// Text between tokens is whitespace, possibly followed by a comment
+fn gap(src: &str, range: Range<usize>, classes: &mut Vec<(Class, Range<usize>)>) {
+    if range.is_empty() {
+        return;
+    }
+    let text = &src[range.clone()];
+    match text.find('#').into_iter().chain(text.find(";;")).min() {
+        Some(i) => {
+            if i > 0 {
+                classes.push((Class::Plain, range.start..range.start + i));
+            }
+            classes.push((Class::Comment, range.start + i..range.end));
+        }
+        None => classes.push((Class::Plain, range)),
+    }
+}
+
+// Completes meta-commands and their arguments, leaving other input to the
+// language
+fn complete_command(before: &str) -> Option<Vec<String>> {
+    if !before.starts_with(':') {
+        return None;
+    }
+    let candidates = match before.split_once(char::is_whitespace) {
+        None => command::NAMES.iter().map(|name| name.to_string()).collect(),
+        Some((":lang", _)) => super::LANGUAGES
+            .iter()
+            .map(|language| language.to_ascii_lowercase())
+            .collect(),
+        Some((":load" | ":save", arg)) => complete_path(arg.trim_start()),
+        // Commands taking source code complete it like any other input
+        Some(_) => return None,
+    };
+    Some(candidates)
+}
+
+// Entries of the directory named by `partial` up to its last `/`; directories
+// end in `/` so completion can carry on into them
+fn complete_path(partial: &str) -> Vec<String> {
+    let dir = match partial.rfind('/') {
+        Some(i) => &partial[..=i],
+        None => "",
+    };
+    let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut candidates: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+            format!("{}{}{}", dir, name, if is_dir { "/" } else { "" })
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+// The candidates starting with the word that ends `before`
+fn matching(before: &str, candidates: Vec<String>) -> Vec<String> {
+    let word = before
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or_default();
+    candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(word))
+        .collect()
+}
+
+// Whether what follows `command` is source code: true for commands that work
+// on it, per the command table, and for a line with no command at all
+fn takes_source(command: &str) -> bool {
+    command.is_empty()
+        || matches!(command::parse(command), Some(Ok(command)) if command.source().is_some())
+}
+
+impl Helper for CalculatorHelper {
+    fn highlight(&self, line: &str) -> String {
+        // A meta-command's name stands out, and any source code after it is
+        // highlighted as usual
+        let (command, src) = match line.find(char::is_whitespace) {
+            Some(i) if line.starts_with(':') => line.split_at(i),
+            None if line.starts_with(':') => (line, ""),
+            _ => ("", line),
+        };
+        let mut highlighted = match command {
+            "" => String::new(),
+            command => Style::new().bold().apply_to(command).to_string(),
+        };
+        if takes_source(command) {
+            for (class, range) in classify(src) {
+                highlighted.push_str(&class.style().apply_to(&src[range]).to_string());
+            }
+        } else {
+            highlighted.push_str(src);
+        }
+        highlighted
+    }
+
+    fn complete(&self, before: &str) -> Vec<String> {
+        let candidates = complete_command(before).unwrap_or_else(|| {
+            // The calculator has no variables, so only its keywords can be named
+            CALCULATOR_BUILTINS
+                .iter()
+                .map(|name| name.to_string())
+                .collect()
+        });
+        matching(before, candidates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classes() {
+        assert_eq!(
+            classify("print + 12 x; 3 # done"),
+            vec![
+                (Class::Keyword, 0..5),
+                (Class::Plain, 5..6),
+                (Class::Operator, 6..7),
+                (Class::Plain, 7..8),
+                (Class::Number, 8..10),
+                (Class::Plain, 10..11),
+                (Class::Error, 11..12),
+                (Class::Separator, 12..13),
+                (Class::Plain, 13..14),
+                (Class::Number, 14..15),
+                (Class::Plain, 15..16),
+                (Class::Comment, 16..22),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_runs() {
+        assert_eq!(
+            classify("1 xyz"),
+            vec![
+                (Class::Number, 0..1),
+                (Class::Plain, 1..2),
+                (Class::Error, 2..5)
+            ]
+        );
+    }
+
+    #[test]
+    fn source_commands() {
+        for command in ["", ":tokens", ":ast", ":time", ":trace"] {
+            assert!(takes_source(command), "{}", command);
+        }
+        for command in [":load", ":save", ":lang", ":help", ":nope"] {
+            assert!(!takes_source(command), "{}", command);
+        }
+    }
+
+    #[test]
+    fn complete_commands() {
+        assert_eq!(
+            CalculatorHelper.complete(":la"),
+            vec![String::from(":lang"), String::from(":langs")]
+        );
+        assert_eq!(
+            CalculatorHelper.complete(":lang c"),
+            vec![String::from("calculator")]
+        );
+    }
+
+    #[test]
+    fn complete_builtins() {
+        assert_eq!(CalculatorHelper.complete("pr"), vec![String::from("print")]);
+        assert_eq!(
+            CalculatorHelper.complete(":time pr"),
+            vec![String::from("print")]
+        );
+        assert!(CalculatorHelper.complete("+ 1 x").is_empty());
+    }
+
+    #[test]
+    fn complete_paths() {
+        let dir = std::env::temp_dir().join(format!("etop-complete-{}", std::process::id()));
+        fs::create_dir_all(dir.join("scripts")).unwrap();
+        fs::write(dir.join("scripts").join("main.calc"), "+ 1 2\n").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        let dir_name = dir.display();
+
+        let completed = CalculatorHelper.complete(&format!(":load {}/sc", dir_name));
+        assert_eq!(completed, vec![format!("{}/scripts/", dir_name)]);
+        let completed = CalculatorHelper.complete(&format!(":save {}/scripts/ma", dir_name));
+        assert_eq!(completed, vec![format!("{}/scripts/main.calc", dir_name)]);
+        let completed = CalculatorHelper.complete(&format!(":load {}/", dir_name));
+        assert_eq!(
+            completed,
+            vec![
+                format!("{}/notes.txt", dir_name),
+                format!("{}/scripts/", dir_name)
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// How many entries a history keeps; older ones are forgotten, in memory and in
+/// its file.
+const MAX_ENTRIES: usize = 1000;
+
+/// Lines previously entered at the prompt, oldest first. When backed by a file,
+/// each new line is appended to it as soon as it is accepted.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    /// A history that lives only as long as the session.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the history kept at `path`. A missing or unreadable file starts an
+    /// empty history that will be created on the first new entry.
+    pub fn load(path: PathBuf) -> Self {
+        let mut entries: Vec<String> = fs::read_to_string(&path)
+            .map(|contents| contents.lines().map(String::from).collect())
+            .unwrap_or_default();
+        if entries.len() > MAX_ENTRIES {
+            entries.drain(..entries.len() - MAX_ENTRIES);
+            let _ = rewrite(&path, &entries);
+        }
+        History {
+            entries,
+            path: Some(path),
+        }
+    }
+
+    /// The history for `language`, kept under the user's config directory.
+    pub fn for_language(language: &str) -> Self {
+        match super::config_dir() {
+            Some(dir) => Self::load(dir.join("history").join(language.to_ascii_lowercase())),
+            None => Self::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    /// Records an accepted line, skipping blanks and immediate repeats.
+    pub fn add(&mut self, line: &str) {
+        if line.trim().is_empty() || self.entries.last().map(String::as_str) == Some(line) {
+            return;
+        }
+        self.entries.push(line.to_string());
+        let full = self.entries.len() > MAX_ENTRIES;
+        if full {
+            self.entries.remove(0);
+        }
+        if let Some(path) = &self.path {
+            // Losing history is not worth interrupting the session over
+            let _ = if full {
+                rewrite(path, &self.entries)
+            } else {
+                append(path, line)
+            };
+        }
+    }
+
+    /// The newest entry before `before` containing `query`.
+    pub fn search(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .rposition(|entry| entry.contains(query))
+    }
+}
+
+fn append(path: &PathBuf, line: &str) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+fn rewrite(path: &PathBuf, entries: &[String]) -> std::io::Result<()> {
+    let mut contents = entries.join("\n");
+    contents.push('\n');
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(entries: &[&str]) -> History {
+        let mut history = History::new();
+        for entry in entries {
+            history.add(entry);
+        }
+        history
+    }
+
+    #[test]
+    fn skips_blanks_and_repeats() {
+        let history = history(&["+ 1 2", "", "+ 1 2", "* 3 4", "+ 1 2"]);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.get(2), Some("+ 1 2"));
+    }
+
+    #[test]
+    fn search_backwards() {
+        let history = history(&["+ 1 2", "* 3 4", "+ 5 6", "/ 8 2"]);
+        assert_eq!(history.search("+", history.len()), Some(2));
+        assert_eq!(history.search("+", 2), Some(0));
+        assert_eq!(history.search("+", 0), None);
+        assert_eq!(history.search("7", history.len()), None);
+    }
+
+    #[test]
+    fn persists() {
+        let path = std::env::temp_dir().join(format!("etop-history-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut history = History::load(path.clone());
+        history.add("+ 1 2");
+        history.add("* 3 4");
+        let reloaded = History::load(path.clone());
+        assert_eq!(reloaded.len(), 2);
+        assert_eq!(reloaded.get(1), Some("* 3 4"));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn capped() {
+        let path = std::env::temp_dir().join(format!("etop-history-cap-{}", std::process::id()));
+        let lines: Vec<String> = (0..MAX_ENTRIES + 5).map(|i| format!("+ {} 1", i)).collect();
+        fs::write(&path, lines.join("\n")).unwrap();
+
+        let mut history = History::load(path.clone());
+        assert_eq!(history.len(), MAX_ENTRIES);
+        assert_eq!(history.get(0), Some("+ 5 1"));
+        history.add("* 2 2");
+        assert_eq!(history.len(), MAX_ENTRIES);
+        assert_eq!(history.get(0), Some("+ 6 1"));
+        let kept = fs::read_to_string(&path).unwrap();
+        assert_eq!(kept.lines().count(), MAX_ENTRIES);
+        assert_eq!(kept.lines().last(), Some("* 2 2"));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
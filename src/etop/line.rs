@@ -0,0 +1,450 @@
+use super::helper::{CalculatorHelper, Helper};
+use super::history::History;
+use console::{Key, Term};
+use dialoguer::Select;
+use std::collections::HashMap;
+use std::io::{self, stdin, stdout, BufRead, IsTerminal, Read, Write};
+
+/// What came of asking for a line of input.
+#[derive(PartialEq, Debug)]
+pub enum Line {
+    Input(String),
+    /// Ctrl-C: the line was abandoned
+    Interrupted,
+    /// Ctrl-D on an empty line, or the end of piped input
+    Eof,
+}
+
+/// Whether etop is talking to a person, as opposed to piped input or output.
+pub fn interactive() -> bool {
+    stdin().is_terminal() && stdout().is_terminal()
+}
+
+/// Where the read-eval-print loop gets its lines from.
+pub trait Input {
+    /// Reads a line of `language` input, after showing `prompt` if there is
+    /// anyone to see it.
+    fn read_line(&mut self, prompt: &str, language: &str) -> io::Result<Line>;
+}
+
+impl<I: Input + ?Sized> Input for Box<I> {
+    fn read_line(&mut self, prompt: &str, language: &str) -> io::Result<Line> {
+        (**self).read_line(prompt, language)
+    }
+}
+
+/// Reads lines one after another from any reader, without prompting.
+pub struct Reader<R> {
+    reader: R,
+    /// The most bytes a line may have, newline included
+    limit: Option<u64>,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(reader: R) -> Self {
+        Reader {
+            reader,
+            limit: None,
+        }
+    }
+
+    /// Fails on lines longer than `bytes`, rather than reading on for as long
+    /// as the other end cares to send.
+    pub fn limit(mut self, bytes: u64) -> Self {
+        self.limit = Some(bytes);
+        self
+    }
+}
+
+impl<R: BufRead> Input for Reader<R> {
+    fn read_line(&mut self, _prompt: &str, _language: &str) -> io::Result<Line> {
+        let mut input = String::new();
+        let limit = self.limit.unwrap_or(u64::MAX);
+        let read = (&mut self.reader).take(limit).read_line(&mut input)?;
+        if read == 0 {
+            Ok(Line::Eof)
+        } else if read as u64 == limit && !input.ends_with('\n') {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("a line is longer than {} bytes", limit),
+            ))
+        } else {
+            Ok(Line::Input(input))
+        }
+    }
+}
+
+/// Reads lines one after another, writing each prompt first, for someone at the
+/// other end of e.g. a socket.
+pub struct Prompted<R, W> {
+    reader: Reader<R>,
+    writer: W,
+}
+
+impl<R: BufRead, W: Write> Prompted<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Prompted {
+            reader: Reader::new(reader),
+            writer,
+        }
+    }
+
+    /// As [`Reader::limit`].
+    pub fn limit(mut self, bytes: u64) -> Self {
+        self.reader = self.reader.limit(bytes);
+        self
+    }
+}
+
+impl<R: BufRead, W: Write> Input for Prompted<R, W> {
+    fn read_line(&mut self, prompt: &str, language: &str) -> io::Result<Line> {
+        self.writer.write_all(prompt.as_bytes())?;
+        self.writer.flush()?;
+        self.reader.read_line(prompt, language)
+    }
+}
+
+/// Reads lines from the terminal with a separate editor, and so a separate
+/// history, for each language.
+#[derive(Default)]
+pub struct Terminal {
+    editors: HashMap<String, Editor>,
+}
+
+impl Terminal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Input for Terminal {
+    fn read_line(&mut self, prompt: &str, language: &str) -> io::Result<Line> {
+        self.editors
+            .entry(language.to_string())
+            .or_insert_with(|| {
+                Editor::new(History::for_language(language), Box::new(CalculatorHelper))
+            })
+            .read_line(prompt)
+    }
+}
+
+/// Reads lines with emacs-style editing, history, reverse search, highlighting
+/// and completion.
+struct Editor {
+    history: History,
+    helper: Box<dyn Helper>,
+}
+
+// An in-progress Ctrl-R search: the query typed so far and the history entry it
+// currently matches
+struct Search {
+    query: String,
+    found: Option<usize>,
+}
+
+impl Editor {
+    fn new(history: History, helper: Box<dyn Helper>) -> Self {
+        Editor { history, helper }
+    }
+
+    // Reads a line after showing `prompt`, adding it to the history if accepted
+    fn read_line(&mut self, prompt: &str) -> io::Result<Line> {
+        let line = self.read_tty(prompt)?;
+        if let Line::Input(input) = &line {
+            self.history.add(input);
+        }
+        Ok(line)
+    }
+
+    // Reads key by key in raw mode, so Ctrl-C reaches us as a key rather than as
+    // SIGINT
+    fn read_tty(&mut self, prompt: &str) -> io::Result<Line> {
+        let term = Term::stdout();
+        let mut buf: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // Position in the history while browsing it, and the line being written
+        // before browsing began
+        let mut browsing = self.history.len();
+        let mut draft: Vec<char> = Vec::new();
+        let mut search: Option<Search> = None;
+
+        self.render(&term, prompt, &buf, cursor)?;
+        loop {
+            let key = term.read_key_raw()?;
+
+            if let Some(Search { query, found }) = &mut search {
+                match key {
+                    Key::Char('\u{12}') => {
+                        let before = found.unwrap_or(self.history.len());
+                        *found = self.history.search(query, before).or(*found);
+                    }
+                    Key::Char(c) if !c.is_control() => {
+                        query.push(c);
+                        let before = found.map_or(self.history.len(), |i| i + 1);
+                        *found = self.history.search(query, before);
+                    }
+                    Key::Backspace => {
+                        query.pop();
+                        *found = self.history.search(query, self.history.len());
+                    }
+                    Key::CtrlC => {
+                        term.write_line("^C")?;
+                        return Ok(Line::Interrupted);
+                    }
+                    key => {
+                        if let Some(entry) = found.and_then(|i| self.history.get(i)) {
+                            buf = entry.chars().collect();
+                            cursor = buf.len();
+                        }
+                        search = None;
+                        if key == Key::Enter {
+                            term.write_line("")?;
+                            return Ok(Line::Input(buf.into_iter().collect()));
+                        }
+                        self.render(&term, prompt, &buf, cursor)?;
+                        continue;
+                    }
+                }
+                let matched = found.and_then(|i| self.history.get(i)).unwrap_or_default();
+                term.clear_line()?;
+                term.write_str(&format!("(reverse-i-search)`{}': {}", query, matched))?;
+                term.flush()?;
+                continue;
+            }
+
+            match action(&key, &buf, cursor) {
+                Action::Submit => {
+                    term.write_line("")?;
+                    return Ok(Line::Input(buf.into_iter().collect()));
+                }
+                Action::Interrupt => {
+                    term.write_line("^C")?;
+                    return Ok(Line::Interrupted);
+                }
+                Action::Eof => {
+                    term.write_line("")?;
+                    return Ok(Line::Eof);
+                }
+                Action::Insert(c) => {
+                    buf.insert(cursor, c);
+                    cursor += 1;
+                }
+                Action::DeleteForward => {
+                    buf.remove(cursor);
+                }
+                Action::DeleteBack => {
+                    cursor -= 1;
+                    buf.remove(cursor);
+                }
+                Action::Left => cursor = cursor.saturating_sub(1),
+                Action::Right => cursor = (cursor + 1).min(buf.len()),
+                Action::Home => cursor = 0,
+                Action::End => cursor = buf.len(),
+                Action::KillToEnd => buf.truncate(cursor),
+                Action::KillToStart => {
+                    buf.drain(..cursor);
+                    cursor = 0;
+                }
+                Action::Older if browsing > 0 => {
+                    if browsing == self.history.len() {
+                        draft = buf.clone();
+                    }
+                    browsing -= 1;
+                    buf = self
+                        .history
+                        .get(browsing)
+                        .unwrap_or_default()
+                        .chars()
+                        .collect();
+                    cursor = buf.len();
+                }
+                Action::Newer if browsing < self.history.len() => {
+                    browsing += 1;
+                    buf = match self.history.get(browsing) {
+                        Some(entry) => entry.chars().collect(),
+                        None => draft.clone(),
+                    };
+                    cursor = buf.len();
+                }
+                Action::Search => {
+                    search = Some(Search {
+                        query: String::new(),
+                        found: None,
+                    });
+                    term.clear_line()?;
+                    term.write_str("(reverse-i-search)`': ")?;
+                    term.flush()?;
+                    continue;
+                }
+                Action::Complete => {
+                    let before: String = buf[..cursor].iter().collect();
+                    let word = before
+                        .rsplit(char::is_whitespace)
+                        .next()
+                        .unwrap_or_default();
+                    let candidates = self.helper.complete(&before);
+                    let completion = match &candidates[..] {
+                        [] => String::new(),
+                        // A directory is only part of the way to a file
+                        [only] if only.ends_with('/') => only.clone(),
+                        [only] => format!("{} ", only),
+                        _ => common_prefix(&candidates),
+                    };
+                    match completion.strip_prefix(word) {
+                        Some(rest) if !rest.is_empty() => {
+                            for c in rest.chars() {
+                                buf.insert(cursor, c);
+                                cursor += 1;
+                            }
+                        }
+                        // Nothing more can be filled in, so show the choices
+                        _ if candidates.len() > 1 => {
+                            term.write_line("")?;
+                            term.write_line(&candidates.join("  "))?;
+                        }
+                        _ => (),
+                    }
+                }
+                Action::ClearScreen => term.clear_screen()?,
+                Action::Older | Action::Newer | Action::Nothing => (),
+            }
+            self.render(&term, prompt, &buf, cursor)?;
+        }
+    }
+
+    // Redraws the whole line and puts the cursor back where it belongs
+    fn render(&self, term: &Term, prompt: &str, buf: &[char], cursor: usize) -> io::Result<()> {
+        term.clear_line()?;
+        term.write_str(prompt)?;
+        term.write_str(&self.helper.highlight(&buf.iter().collect::<String>()))?;
+        term.move_cursor_left(buf.len() - cursor)?;
+        term.flush()
+    }
+}
+
+/// What a key does to the line being edited, outside of a Ctrl-R search.
+#[derive(PartialEq, Debug)]
+enum Action {
+    Submit,
+    Interrupt,
+    Eof,
+    Insert(char),
+    DeleteForward,
+    DeleteBack,
+    Left,
+    Right,
+    Home,
+    End,
+    KillToEnd,
+    KillToStart,
+    /// Back through the history, if there is further to go
+    Older,
+    Newer,
+    Search,
+    Complete,
+    ClearScreen,
+    Nothing,
+}
+
+// The emacs-style binding of `key`, given the line `buf` with the cursor at
+// `cursor`: Ctrl-D ends input on an empty line and deletes forward otherwise
+fn action(key: &Key, buf: &[char], cursor: usize) -> Action {
+    match key {
+        Key::Enter => Action::Submit,
+        Key::CtrlC => Action::Interrupt,
+        Key::Char('\u{4}') if buf.is_empty() => Action::Eof,
+        Key::Char('\u{4}') | Key::Del if cursor < buf.len() => Action::DeleteForward,
+        Key::Backspace if cursor > 0 => Action::DeleteBack,
+        Key::ArrowLeft | Key::Char('\u{2}') => Action::Left,
+        Key::ArrowRight | Key::Char('\u{6}') => Action::Right,
+        Key::Home => Action::Home,
+        Key::End => Action::End,
+        Key::Char('\u{b}') => Action::KillToEnd,
+        Key::Char('\u{15}') => Action::KillToStart,
+        Key::ArrowUp | Key::Char('\u{10}') => Action::Older,
+        Key::ArrowDown | Key::Char('\u{e}') => Action::Newer,
+        Key::Char('\u{12}') => Action::Search,
+        Key::Tab => Action::Complete,
+        Key::Char('\u{c}') => Action::ClearScreen,
+        Key::Char(c) if !c.is_control() => Action::Insert(*c),
+        _ => Action::Nothing,
+    }
+}
+
+/// Asks which of `languages` to use from a menu; `None` means the menu was
+/// dismissed with Esc or `q`.
+pub fn choose_language(languages: &[&'static str]) -> Result<Option<&'static str>, String> {
+    let selection = Select::new()
+        .with_prompt("Please select a language")
+        .default(0)
+        .items(languages)
+        .interact_opt()
+        .map_err(|e| e.to_string())?;
+    Ok(picked(languages, selection))
+}
+
+fn picked(languages: &[&'static str], selection: Option<usize>) -> Option<&'static str> {
+    selection.and_then(|i| languages.get(i).copied())
+}
+
+fn common_prefix(candidates: &[String]) -> String {
+    let mut prefix = candidates[0].clone();
+    for candidate in &candidates[1..] {
+        while !candidate.starts_with(&prefix) {
+            prefix.pop();
+        }
+    }
+    prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_limit() {
+        let mut reader = Reader::new("+ 1 2\n* 3 4 5 6\n".as_bytes()).limit(8);
+        assert_eq!(
+            reader.read_line("", "").unwrap(),
+            Line::Input(String::from("+ 1 2\n"))
+        );
+        let err = reader.read_line("", "").unwrap_err();
+        assert_eq!(err.to_string(), "a line is longer than 8 bytes");
+        // Exactly at the limit is fine
+        let mut reader = Reader::new("1234567\n".as_bytes()).limit(8);
+        assert!(matches!(reader.read_line("", ""), Ok(Line::Input(_))));
+    }
+
+    #[test]
+    fn ctrl_c_and_ctrl_d() {
+        let line: Vec<char> = "+ 1 2".chars().collect();
+        assert_eq!(action(&Key::CtrlC, &[], 0), Action::Interrupt);
+        assert_eq!(action(&Key::CtrlC, &line, 2), Action::Interrupt);
+        assert_eq!(action(&Key::Char('\u{4}'), &[], 0), Action::Eof);
+        assert_eq!(action(&Key::Char('\u{4}'), &line, 0), Action::DeleteForward);
+        // At the end of a line there is nothing to delete, and input goes on
+        assert_eq!(action(&Key::Char('\u{4}'), &line, 5), Action::Nothing);
+    }
+
+    #[test]
+    fn editing_keys() {
+        let line: Vec<char> = "ab".chars().collect();
+        assert_eq!(action(&Key::Enter, &line, 1), Action::Submit);
+        assert_eq!(action(&Key::Char('x'), &line, 1), Action::Insert('x'));
+        assert_eq!(action(&Key::Backspace, &line, 0), Action::Nothing);
+        assert_eq!(action(&Key::Backspace, &line, 1), Action::DeleteBack);
+        assert_eq!(action(&Key::Char('\u{b}'), &line, 1), Action::KillToEnd);
+        assert_eq!(action(&Key::ArrowUp, &line, 1), Action::Older);
+        assert_eq!(action(&Key::Char('\u{12}'), &line, 1), Action::Search);
+        assert_eq!(action(&Key::Tab, &line, 1), Action::Complete);
+        assert_eq!(action(&Key::Char('\u{7}'), &line, 1), Action::Nothing);
+    }
+
+    #[test]
+    fn language_menu() {
+        let languages = ["Calculator", "Other"];
+        assert_eq!(picked(&languages, Some(1)), Some("Other"));
+        // Esc or `q` dismisses the menu, and etop exits without a language
+        assert_eq!(picked(&languages, None), None);
+    }
+}
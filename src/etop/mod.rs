@@ -1,52 +1,586 @@
-use dialoguer::Select;
-use languages::languages::calculator::{interpreter, lexer, parser, CalculatorError};
-use std::io::{stdin, stdout, Write};
-
-pub fn etop() {
-    let languages = ["Calculator"];
-
-    let selection = Select::new()
-        .with_prompt("Please select a language")
-        .default(0)
-        .items(&languages[..])
-        .interact()
-        .unwrap();
+use crate::languages::calculator::interpreter::{self, Numeric};
+use crate::languages::calculator::{lexer, parser, pretty, CalculatorError, Stmt};
+use crate::languages::diagnostic::Diagnostic;
+use crate::languages::error;
+use crate::languages::io::{InterpreterIo, Scripted};
+use std::collections::hash_map::{Entry, HashMap};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, stdin, stdout, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+mod command;
+pub mod config;
+mod helper;
+mod history;
+mod line;
+pub mod server;
+pub mod transcript;
+
+use command::Command;
+use config::Config;
+pub use line::{Input, Line, Prompted, Reader, Terminal};
+use transcript::{Recording, Tee};
+
+/// How etop reports lexer, parser and interpreter errors.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ErrorFormat {
+    /// The offending source line with a `^~~` underline, then the message
+    Human,
+    /// One JSON diagnostic per line, for editors and CI. etop writes these to
+    /// stderr, so that its stdout holds only results.
+    Json,
+}
+
+const LANGUAGES: [&str; 1] = ["Calculator"];
+
+const PROMPT: &str = "etop # ";
+/// Prompt for each further line of an unfinished input
+const CONTINUATION: &str = "...  ";
+
+// Looks a language up by name, ignoring case
+fn find_language(name: &str) -> Result<&'static str, String> {
+    LANGUAGES
+        .iter()
+        .find(|language| language.eq_ignore_ascii_case(name))
+        .copied()
+        .ok_or_else(|| {
+            format!(
+                "unknown language `{}`; expected one of: {}",
+                name,
+                LANGUAGES.join(", ")
+            )
+        })
+}
+
+/// Settings for a run of the read-eval-print loop.
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub error_format: ErrorFormat,
+    /// Whether to write each input line to the output after its prompt, as a
+    /// terminal would have shown it
+    pub echo: bool,
+    /// Shown when etop is ready for a new input
+    pub prompt: String,
+    /// Put before each result
+    pub result_prefix: String,
+    pub numeric: Numeric,
+    /// Scripts to run when a language is first used, by language
+    pub startup: HashMap<&'static str, PathBuf>,
+    /// Whether `:load` and `:save` may use files, which they do with etop's
+    /// own permissions
+    pub files: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            error_format: ErrorFormat::Human,
+            echo: false,
+            prompt: String::from(PROMPT),
+            result_prefix: String::from("- : "),
+            numeric: Numeric::default(),
+            startup: HashMap::new(),
+            files: true,
+        }
+    }
+}
+
+/// Everything etop remembers about one language, so that switching away and
+/// back again picks up where the user left off.
+#[derive(Default)]
+struct Session {
+    /// Input so far of an expression that is being continued over several lines
+    pending: String,
+    /// Every input that ran without error, for `:save`
+    accepted: Vec<String>,
+}
+
+/// Runs etop on the terminal, or on piped input, until `:quit` or end of
+/// input, with the settings from the config file. The language is picked from
+/// a menu unless one is named here or in the config file, which it must be when
+/// etop is not attached to a terminal. With `record`, the session is also
+/// written to that file as a transcript.
+pub fn etop(
+    language: Option<&str>,
+    error_format: ErrorFormat,
+    record: Option<&str>,
+) -> Result<(), String> {
+    let config = Config::load()?;
+    let language = match language.map(find_language).transpose()?.or(config.language) {
+        Some(language) => language,
+        None if line::interactive() => match line::choose_language(&LANGUAGES)? {
+            Some(language) => language,
+            None => return Ok(()),
+        },
+        None => return Err(String::from("no language given and no terminal to ask on")),
+    };
+
+    if line::interactive() {
+        println!("Language chosen: {}", language);
+    }
+    console::set_colors_enabled(color_enabled(config.color));
+    let mut input: Box<dyn Input> = if line::interactive() {
+        Box::new(Terminal::new())
+    } else {
+        Box::new(Reader::new(stdin().lock()))
+    };
+    let mut out: Box<dyn Write> = Box::new(stdout());
+    if let Some(path) = record {
+        let recorded = || -> io::Result<(Recording<_>, Tee<_>)> {
+            let mut file = File::create(path)?;
+            writeln!(file, "Language chosen: {}", language)?;
+            Ok((
+                Recording::new(input, file.try_clone()?),
+                Tee::new(out, file),
+            ))
+        };
+        let (recording, tee) =
+            recorded().map_err(|e| format!("could not record to `{}`: {}", path, e))?;
+        input = Box::new(recording);
+        out = Box::new(tee);
+    }
+    let options = Options {
+        error_format,
+        ..config.options
+    };
+    let mut stderr = io::stderr();
+    let diagnostics: Option<&mut dyn Write> = match error_format {
+        ErrorFormat::Json => Some(&mut stderr),
+        ErrorFormat::Human => None,
+    };
+    repl(&mut *input, &mut out, diagnostics, language, &options).map_err(|e| e.to_string())
+}
 
-    let language = &languages[selection];
-    println!("Language chosen: {}", language);
+/// Runs the read-eval-print loop over `input` until `:quit` or end of input,
+/// writing everything it has to say to `output`, as etop does with stdin and
+/// stdout.
+pub fn run<R: BufRead, W: Write>(
+    input: R,
+    mut output: W,
+    language: &str,
+    options: &Options,
+) -> Result<(), String> {
+    let language = find_language(language)?;
+    repl(
+        &mut Reader::new(input),
+        &mut output,
+        None,
+        language,
+        options,
+    )
+    .map_err(|e| e.to_string())
+}
 
-    loop {
-        print!("etop # ");
-        stdout().flush().expect("flush error");
+// Errors are reported to `diagnostics` if given, and otherwise to `out` with
+// everything else
+fn repl(
+    input: &mut dyn Input,
+    out: &mut dyn Write,
+    mut diagnostics: Option<&mut dyn Write>,
+    mut language: &'static str,
+    options: &Options,
+) -> io::Result<()> {
+    let mut sessions: HashMap<&str, Session> = HashMap::new();
+    let mut eof = false;
 
-        let mut input = String::new();
-        stdin().read_line(&mut input).unwrap();
+    while !eof {
+        if let Entry::Vacant(entry) = sessions.entry(language) {
+            entry.insert(Session::default());
+            if let Some(path) = options.startup.get(language) {
+                startup(path, language, out, diagnostics.as_deref_mut(), options)?;
+            }
+        }
+        let Session { pending, accepted } = sessions
+            .get_mut(language)
+            .expect("session was just created");
+        let prompt = if pending.is_empty() {
+            options.prompt.as_str()
+        } else {
+            CONTINUATION
+        };
+        let input = match input.read_line(prompt, language)? {
+            Line::Input(input) => {
+                if options.echo {
+                    writeln!(out, "{}{}", prompt, input.trim_end_matches(['\n', '\r']))?;
+                }
+                input
+            }
+            Line::Interrupted => {
+                pending.clear();
+                continue;
+            }
+            // Still report whatever was left unfinished
+            Line::Eof if !pending.is_empty() => {
+                eof = true;
+                String::new()
+            }
+            Line::Eof => break,
+        };
         let input_clone = input.clone().trim().to_string();
+        let continuing = !pending.is_empty();
 
-        let result = match *language {
-            "Calculator" => match etop_calculator(input_clone.as_str()) {
-                Ok(str_result) => str_result,
-                Err(e) => match e {
-                    CalculatorError::Parser(parser_error) => {
-                        format!("Parser error: {:?}", parser_error)
+        // The file errors should be located in, when not the input itself
+        let mut origin = None;
+        // Commands work mid-continuation too, e.g. to switch language and come
+        // back to the unfinished input later
+        let (src, evaluated) = match command::parse(&input_clone) {
+            Some(Ok(Command::Quit)) => break,
+            Some(Ok(Command::Help)) => {
+                writeln!(out, "{}", command::HELP)?;
+                continue;
+            }
+            Some(Ok(Command::Lang(name))) => {
+                match find_language(&name) {
+                    Ok(found) => {
+                        language = found;
+                        writeln!(out, "Language chosen: {}", language)?;
+                    }
+                    Err(message) => writeln!(out, "{}", message)?,
+                }
+                continue;
+            }
+            Some(Ok(Command::Langs)) => {
+                for available in LANGUAGES {
+                    let marker = if available == language { "*" } else { " " };
+                    writeln!(out, "{} {}", marker, available)?;
+                }
+                continue;
+            }
+            Some(Ok(Command::Tokens(src))) => {
+                let evaluated = match language {
+                    "Calculator" => etop_tokens(&src, out),
+                    _ => Ok(()),
+                };
+                (src, evaluated)
+            }
+            Some(Ok(Command::Ast(src))) => {
+                let evaluated = match language {
+                    "Calculator" => etop_ast(&src, out),
+                    _ => Ok(()),
+                };
+                (src, evaluated)
+            }
+            Some(Ok(Command::Time(src))) => {
+                let evaluated = match language {
+                    "Calculator" => etop_time(&src, out, options.numeric, &options.result_prefix),
+                    _ => Ok(()),
+                };
+                (src, evaluated)
+            }
+            Some(Ok(Command::Trace(src))) => {
+                let evaluated = match language {
+                    "Calculator" => etop_trace(&src, out, options.numeric),
+                    _ => Ok(()),
+                };
+                (src, evaluated)
+            }
+            Some(Ok(Command::Load(_) | Command::Save(_))) if !options.files => {
+                writeln!(out, "`:load` and `:save` are turned off in this session")?;
+                continue;
+            }
+            Some(Ok(Command::Load(path))) => {
+                let src = match fs::read_to_string(&path) {
+                    Ok(src) => src,
+                    Err(e) => {
+                        writeln!(out, "could not read `{}`: {}", path, e)?;
+                        continue;
+                    }
+                };
+                let evaluated = match language {
+                    "Calculator" => {
+                        etop_calculator(&src, out, options.numeric, Some(&options.result_prefix))
                     }
-                    CalculatorError::Lexer(lexer_error) => {
-                        format!("Lexer error: {:?}", lexer_error)
+                    _ => Ok(()),
+                };
+                if evaluated.is_ok() {
+                    accepted.push(src.trim_end().to_string());
+                }
+                origin = Some(path);
+                (src, evaluated)
+            }
+            Some(Ok(Command::Save(path))) => {
+                let mut script = accepted.join("\n");
+                script.push('\n');
+                match fs::write(&path, script) {
+                    Ok(()) => writeln!(out, "saved {} inputs to `{}`", accepted.len(), path)?,
+                    Err(e) => writeln!(out, "could not write `{}`: {}", path, e)?,
+                }
+                continue;
+            }
+            Some(Err(message)) => {
+                writeln!(out, "{}", message)?;
+                continue;
+            }
+            None => {
+                // Continued lines join with a space: a newline would end the
+                // statement
+                let src = if continuing && !input_clone.is_empty() {
+                    format!("{} {}", pending, input_clone)
+                } else if continuing {
+                    pending.clone()
+                } else {
+                    input_clone
+                };
+                let evaluated = match language {
+                    "Calculator" => {
+                        etop_calculator(&src, out, options.numeric, Some(&options.result_prefix))
                     }
-                    CalculatorError::Interpreter(interpreter_error) => {
-                        format!("Interpreter error: {:?}", interpreter_error)
+                    _ => Ok(()),
+                };
+                pending.clear();
+                match evaluated {
+                    Err(e) if !eof && incomplete(&e, &src) => {
+                        *pending = src;
+                        continue;
                     }
-                },
-            },
-            _ => String::from(""),
+                    Ok(()) => {
+                        accepted.push(src.clone());
+                        (src, Ok(()))
+                    }
+                    evaluated => (src, evaluated),
+                }
+            }
         };
-        println!("- : {}", result);
+
+        if let Err(e) = evaluated {
+            match diagnostics.as_deref_mut() {
+                Some(to) => report(to, &e, &src, origin.as_deref(), options)?,
+                None => report(out, &e, &src, origin.as_deref(), options)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+// Runs a language's startup script, reporting only what goes wrong
+fn startup<'d>(
+    path: &Path,
+    language: &str,
+    out: &mut dyn Write,
+    diagnostics: Option<&mut (dyn Write + 'd)>,
+    options: &Options,
+) -> io::Result<()> {
+    let src = match fs::read_to_string(path) {
+        Ok(src) => src,
+        Err(e) => {
+            return writeln!(
+                out,
+                "could not read startup script `{}`: {}",
+                path.display(),
+                e
+            )
+        }
+    };
+    let evaluated = match language {
+        "Calculator" => etop_calculator(&src, out, options.numeric, None),
+        _ => Ok(()),
+    };
+    match evaluated {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let origin = path.display().to_string();
+            match diagnostics {
+                Some(to) => report(to, &e, &src, Some(&origin), options),
+                None => report(out, &e, &src, Some(&origin), options),
+            }
+        }
+    }
+}
+
+// Shows an error in `src`, which came from the file `origin` if not typed in
+fn report(
+    out: &mut dyn Write,
+    e: &CalculatorError,
+    src: &str,
+    origin: Option<&str>,
+    options: &Options,
+) -> io::Result<()> {
+    let diagnostic = Diagnostic::from(e);
+    match options.error_format {
+        ErrorFormat::Human => write!(
+            out,
+            "{}",
+            diagnostic.render(src, origin, &options.result_prefix)
+        ),
+        ErrorFormat::Json => writeln!(out, "{}", diagnostic.to_json()),
+    }
+}
+
+// Whether the parser ran out of input at the very end of `src`, meaning more
+// lines could complete it (as opposed to e.g. `+ 1; 2`, which never will)
+fn incomplete(e: &CalculatorError, src: &str) -> bool {
+    match e {
+        CalculatorError::Parser(err) => {
+            err.is_tokens_empty() && err.span().is_some_and(|span| span.start >= src.len())
+        }
+        _ => false,
+    }
+}
+
+/// Colour is for people: off when output is redirected, and otherwise as
+/// configured, or unless `NO_COLOR` is set.
+fn color_enabled(configured: Option<bool>) -> bool {
+    line::interactive()
+        && configured
+            .unwrap_or_else(|| env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()))
+}
+
+/// Where etop keeps its files: `$XDG_CONFIG_HOME/etop`, falling back to
+/// `~/.config/etop` (`%APPDATA%\etop` on Windows).
+pub fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(base.join("etop"))
+}
+
+// Runs each statement in turn, writing an expression statement's value after
+// `prefix` as it finishes, or nothing without one; `print` output goes straight
+// to `out`, and there is no input to read from under the prompt
+fn etop_calculator(
+    input: &str,
+    out: &mut dyn Write,
+    numeric: Numeric,
+    prefix: Option<&str>,
+) -> Result<(), CalculatorError> {
+    let lexed = lexer::lexer(input, 0).map_err(CalculatorError::Lexer)?;
+    let parsed = parser::program(lexed).map_err(CalculatorError::Parser)?;
+    let mut io = Scripted::new(out);
+    for stmt in parsed {
+        let interpreted = interpreter::statement_with(stmt, numeric, &mut io)
+            .map_err(CalculatorError::Interpreter)?;
+        if let (Some(value), Some(prefix)) = (interpreted, prefix) {
+            io.write_str(&format!("{}{}\n", prefix, value))
+                .map_err(write_error)?;
+        }
+    }
+    Ok(())
+}
+
+// Failing to show a listing is failing to write output, as for `print`
+fn write_error(e: io::Error) -> CalculatorError {
+    CalculatorError::Interpreter(error::interpreter::Error::io(&e))
+}
+
+fn etop_tokens(input: &str, out: &mut dyn Write) -> Result<(), CalculatorError> {
+    let lexed = lexer::lexer(input, 0).map_err(CalculatorError::Lexer)?;
+    for tok in lexed {
+        writeln!(out, "{:<6} {:?}", tok.span.to_string(), tok.node).map_err(write_error)?;
+    }
+    Ok(())
+}
+
+fn etop_ast(input: &str, out: &mut dyn Write) -> Result<(), CalculatorError> {
+    let lexed = lexer::lexer(input, 0).map_err(CalculatorError::Lexer)?;
+    let parsed = parser::program(lexed).map_err(CalculatorError::Parser)?;
+    write!(out, "{}", pretty::tree(&parsed)).map_err(write_error)?;
+    Ok(())
+}
+
+fn etop_time(
+    input: &str,
+    out: &mut dyn Write,
+    numeric: Numeric,
+    prefix: &str,
+) -> Result<(), CalculatorError> {
+    let start = Instant::now();
+    let lexed = lexer::lexer(input, 0).map_err(CalculatorError::Lexer)?;
+    let lexed_at = Instant::now();
+    let parsed = parser::program(lexed).map_err(CalculatorError::Parser)?;
+    let parsed_at = Instant::now();
+    let mut io = Scripted::new(out);
+    for stmt in parsed {
+        let interpreted = interpreter::statement_with(stmt, numeric, &mut io)
+            .map_err(CalculatorError::Interpreter)?;
+        if let Some(value) = interpreted {
+            io.write_str(&format!("{}{}\n", prefix, value))
+                .map_err(write_error)?;
+        }
     }
+    let evaluated_at = Instant::now();
+    writeln!(
+        out,
+        "lex: {:?}  parse: {:?}  eval: {:?}",
+        lexed_at - start,
+        parsed_at - lexed_at,
+        evaluated_at - parsed_at
+    )
+    .map_err(write_error)?;
+    Ok(())
 }
 
-fn etop_calculator(input: &str) -> Result<String, CalculatorError> {
+// One line of steps per statement; `print` statements are traced like any other
+fn etop_trace(input: &str, out: &mut dyn Write, numeric: Numeric) -> Result<(), CalculatorError> {
     let lexed = lexer::lexer(input, 0).map_err(CalculatorError::Lexer)?;
-    let parsed = parser::parser(lexed).map_err(CalculatorError::Parser)?;
-    let interpreted = interpreter::interpreter(parsed).map_err(CalculatorError::Interpreter)?;
-    Ok(interpreted.to_string())
+    let parsed = parser::program(lexed).map_err(CalculatorError::Parser)?;
+    for stmt in parsed {
+        let (Stmt::Expr(e) | Stmt::Print(e)) = stmt.node;
+        let steps =
+            interpreter::reductions_with(e, numeric).map_err(CalculatorError::Interpreter)?;
+        let steps: Vec<String> = steps.iter().map(pretty::expr).collect();
+        writeln!(out, "{}", steps.join(" → ")).map_err(write_error)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Whether etop would wait for more lines after `src`
+    fn continues(src: &str) -> bool {
+        let parsed = lexer::lexer(src, 0)
+            .map_err(CalculatorError::Lexer)
+            .and_then(|toks| parser::program(toks).map_err(CalculatorError::Parser));
+        parsed.is_err_and(|e| incomplete(&e, src))
+    }
+
+    #[test]
+    fn json_diagnostics_apart() {
+        let options = Options {
+            error_format: ErrorFormat::Json,
+            ..Options::default()
+        };
+        let (mut out, mut diagnostics) = (Vec::new(), Vec::new());
+        let mut input = Reader::new("+ 1 2\n/ 1 0\n3\n".as_bytes());
+        repl(
+            &mut input,
+            &mut out,
+            Some(&mut diagnostics),
+            "Calculator",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "- : 3\n- : 3\n");
+        let diagnostics = String::from_utf8(diagnostics).unwrap();
+        assert_eq!(diagnostics.lines().count(), 1);
+        assert!(diagnostics.starts_with(r#"{"phase":"interpreter","code":"I001""#));
+    }
+
+    #[test]
+    fn incomplete_input() {
+        // Operators still waiting for operands
+        assert!(continues("+"));
+        assert!(continues("+ 1"));
+        assert!(continues("* 2 -"));
+        assert!(continues("print"));
+    }
+
+    #[test]
+    fn complete_input() {
+        assert!(!continues("+ 1 2"));
+        assert!(!continues("1; 2;"));
+        assert!(!continues("# only a comment"));
+        // Errors before the end of the input are never fixed by more lines
+        assert!(!continues("+ 1;"));
+        assert!(!continues("+ 1; 2"));
+        assert!(!continues("+ 1 2 3"));
+        assert!(!continues("%"));
+        // A continued line would be joined on inside the comment
+        assert!(!continues("+ 1 # the other operand is next"));
+    }
 }
@@ -0,0 +1,283 @@
+use super::config::Config;
+use super::{find_language, repl, ErrorFormat, Options, Prompted, LANGUAGES};
+use std::io::{self, BufReader, Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// The longest line a client may send, newline included
+const MAX_LINE: u64 = 64 * 1024;
+
+/// How many clients may have sessions at once
+const MAX_SESSIONS: usize = 64;
+
+/// A connection a client talks to its session over.
+trait Stream: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+}
+
+impl Stream for std::net::TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        std::net::TcpStream::try_clone(self)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+}
+
+/// Serves etop sessions on `addr` until the process is stopped, with the
+/// settings from the config file. An address containing a `/` is the path of a
+/// Unix socket; anything else is a TCP `host:port`.
+///
+/// Every client gets a session of its own, starting in `language` (or the
+/// configured language, or the first there is) and free to switch with
+/// `:lang`. Clients cannot `:load` or `:save`, which would use files with the
+/// server's permissions. So that no client can starve the others, lines are
+/// limited to 64 KiB and sessions to 64 at a time; further clients are turned
+/// away until one leaves.
+pub fn listen(addr: &str, language: Option<&str>, error_format: ErrorFormat) -> Result<(), String> {
+    let config = Config::load()?;
+    let language = language
+        .map(find_language)
+        .transpose()?
+        .or(config.language)
+        .unwrap_or(LANGUAGES[0]);
+    let options = Options {
+        error_format,
+        ..config.options
+    };
+    let bound = |e: io::Error| format!("could not listen on `{}`: {}", addr, e);
+
+    if addr.contains('/') {
+        #[cfg(unix)]
+        {
+            let listener = bind_unix(addr).map_err(bound)?;
+            eprintln!("etop: listening on {}", addr);
+            serve(listener.incoming(), language, &options, MAX_SESSIONS);
+            return Ok(());
+        }
+        #[cfg(not(unix))]
+        return Err(String::from("Unix sockets are not supported here"));
+    }
+    let listener = TcpListener::bind(addr).map_err(bound)?;
+    eprintln!(
+        "etop: listening on {}",
+        listener.local_addr().map_err(bound)?
+    );
+    serve(listener.incoming(), language, &options, MAX_SESSIONS);
+    Ok(())
+}
+
+// A socket file outlives the server that made it, so one that nothing answers
+// on any more is cleared away first
+#[cfg(unix)]
+fn bind_unix(path: &str) -> io::Result<UnixListener> {
+    match UnixListener::bind(path) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(path).is_err() => {
+            std::fs::remove_file(path)?;
+            UnixListener::bind(path)
+        }
+        bound => bound,
+    }
+}
+
+// Hands each connection to a thread of its own, up to `sessions` at a time. A
+// client that cannot be served is reported, but does not stop the others.
+fn serve<S: Stream>(
+    incoming: impl Iterator<Item = io::Result<S>>,
+    language: &'static str,
+    options: &Options,
+    sessions: usize,
+) {
+    let active = Arc::new(AtomicUsize::new(0));
+    let options = Options {
+        files: false,
+        ..options.clone()
+    };
+    for stream in incoming {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("etop: could not accept a connection: {}", e);
+                continue;
+            }
+        };
+        // Only this loop starts sessions, so the count cannot rise under it
+        if active.load(Ordering::SeqCst) >= sessions {
+            let mut stream = stream;
+            let _ = writeln!(stream, "etop: too many sessions; try again later");
+            continue;
+        }
+        active.fetch_add(1, Ordering::SeqCst);
+        let active = Active(Arc::clone(&active));
+        let options = options.clone();
+        thread::spawn(move || {
+            if let Err(e) = session(stream, language, &options, active) {
+                eprintln!("etop: session ended: {}", e);
+            }
+        });
+    }
+}
+
+// Counts a session as active until it is dropped
+struct Active(Arc<AtomicUsize>);
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn session<S: Stream>(
+    stream: S,
+    language: &'static str,
+    options: &Options,
+    active: Active,
+) -> io::Result<()> {
+    let mut out = stream.try_clone()?;
+    writeln!(out, "Language chosen: {}", language)?;
+    let mut input = Prompted::new(BufReader::new(stream.try_clone()?), stream).limit(MAX_LINE);
+    let ended = repl(&mut input, &mut out, None, language, options);
+    // Free the place before the client sees the connection close
+    drop(active);
+    ended
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    // Reads from `client` until `expected` has arrived
+    fn expect(client: &mut TcpStream, expected: &str) {
+        let mut received = String::new();
+        let mut buf = [0; 256];
+        while !received.contains(expected) {
+            let read = client.read(&mut buf).unwrap();
+            assert!(read > 0, "connection closed; received {:?}", received);
+            received.push_str(std::str::from_utf8(&buf[..read]).unwrap());
+        }
+    }
+
+    fn connect(addr: std::net::SocketAddr) -> TcpStream {
+        let client = TcpStream::connect(addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        client
+    }
+
+    #[test]
+    fn independent_sessions() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            serve(
+                listener.incoming(),
+                "Calculator",
+                &Options::default(),
+                MAX_SESSIONS,
+            )
+        });
+
+        let mut first = connect(addr);
+        let mut second = connect(addr);
+        expect(&mut first, "Language chosen: Calculator\netop # ");
+        expect(&mut second, "Language chosen: Calculator\netop # ");
+
+        // The first client's unfinished input is no business of the second's
+        first.write_all(b"+ 1\n").unwrap();
+        expect(&mut first, "...  ");
+        second.write_all(b"* 2 3\n").unwrap();
+        expect(&mut second, "- : 6\netop # ");
+        first.write_all(b"2\n").unwrap();
+        expect(&mut first, "- : 3\netop # ");
+
+        second.write_all(b":quit\n").unwrap();
+        first.write_all(b"print 7\n").unwrap();
+        expect(&mut first, "7\netop # ");
+    }
+
+    #[test]
+    fn no_files() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            serve(
+                listener.incoming(),
+                "Calculator",
+                &Options::default(),
+                MAX_SESSIONS,
+            )
+        });
+        let path = std::env::temp_dir().join(format!("etop-remote-save-{}", std::process::id()));
+
+        let mut client = connect(addr);
+        expect(&mut client, "etop # ");
+        client.write_all(b"+ 1 2\n").unwrap();
+        expect(&mut client, "- : 3\netop # ");
+        writeln!(client, ":save {}", path.display()).unwrap();
+        expect(
+            &mut client,
+            "`:load` and `:save` are turned off in this session\netop # ",
+        );
+        assert!(!path.exists());
+        writeln!(client, ":load {}", path.display()).unwrap();
+        expect(&mut client, "turned off in this session\netop # ");
+    }
+
+    // Reads from `client` until the server closes the connection
+    fn closed(client: &mut TcpStream) -> String {
+        let mut received = Vec::new();
+        let mut buf = [0; 256];
+        while let Ok(read @ 1..) = client.read(&mut buf) {
+            received.extend_from_slice(&buf[..read]);
+        }
+        String::from_utf8_lossy(&received).into_owned()
+    }
+
+    #[test]
+    fn long_lines() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener.incoming(), "Calculator", &Options::default(), 1));
+
+        let mut client = connect(addr);
+        expect(&mut client, "etop # ");
+        // The server stops reading partway, so the rest may not be sent
+        let _ = client.write_all(&vec![b'1'; MAX_LINE as usize + 1]);
+        assert!(!closed(&mut client).contains("- :"));
+
+        // The session's place is free again
+        let mut client = connect(addr);
+        expect(&mut client, "etop # ");
+    }
+
+    #[test]
+    fn session_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener.incoming(), "Calculator", &Options::default(), 1));
+
+        let mut first = connect(addr);
+        expect(&mut first, "etop # ");
+        let mut second = connect(addr);
+        assert_eq!(
+            closed(&mut second),
+            "etop: too many sessions; try again later\n"
+        );
+
+        first.write_all(b":quit\n").unwrap();
+        closed(&mut first);
+        let mut third = connect(addr);
+        expect(&mut third, "Language chosen: Calculator\netop # ");
+    }
+}
@@ -0,0 +1,181 @@
+use super::{run, Input, Line, Options, CONTINUATION};
+use std::fs::File;
+use std::io::{self, Write};
+
+/// Marks the first line of a transcript, naming the language it is in.
+const HEADER: &str = "Language chosen: ";
+
+/// Input that is also written to a transcript after its prompt, as it appeared
+/// on screen.
+pub struct Recording<I> {
+    input: I,
+    file: File,
+}
+
+impl<I: Input> Recording<I> {
+    pub fn new(input: I, file: File) -> Self {
+        Recording { input, file }
+    }
+}
+
+impl<I: Input> Input for Recording<I> {
+    fn read_line(&mut self, prompt: &str, language: &str) -> io::Result<Line> {
+        let line = self.input.read_line(prompt, language)?;
+        if let Line::Input(input) = &line {
+            writeln!(
+                self.file,
+                "{}{}",
+                prompt,
+                input.trim_end_matches(['\n', '\r'])
+            )?;
+        }
+        Ok(line)
+    }
+}
+
+/// Output that is also written to a transcript.
+pub struct Tee<W> {
+    out: W,
+    file: File,
+}
+
+impl<W: Write> Tee<W> {
+    pub fn new(out: W, file: File) -> Self {
+        Tee { out, file }
+    }
+}
+
+impl<W: Write> Write for Tee<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write_all(buf)?;
+        self.file.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()?;
+        self.file.flush()
+    }
+}
+
+/// Feeds the inputs of a recorded transcript to a fresh session with `options`
+/// and returns the transcript of that session, which matches the original for
+/// as long as etop behaves as it did when it was recorded.
+///
+/// A transcript starts with `Language chosen: <language>`; every line after it
+/// that starts with a prompt is an input, and every other line is output.
+pub fn replay(transcript: &str, options: &Options) -> Result<String, String> {
+    let mut lines = transcript.lines();
+    let language = lines
+        .next()
+        .and_then(|header| header.strip_prefix(HEADER))
+        .ok_or_else(|| format!("a transcript starts with `{}<language>`", HEADER))?;
+    let inputs: String = lines
+        .filter_map(|line| {
+            line.strip_prefix(options.prompt.as_str())
+                .or_else(|| line.strip_prefix(CONTINUATION))
+        })
+        .map(|input| format!("{}\n", input))
+        .collect();
+
+    let mut out = format!("{}{}\n", HEADER, language).into_bytes();
+    let options = Options {
+        echo: true,
+        ..options.clone()
+    };
+    run(inputs.as_bytes(), &mut out, language, &options)?;
+    String::from_utf8(out).map_err(|e| e.to_string())
+}
+
+/// Describes the first line at which `actual` departs from `expected`, if any.
+pub fn difference(expected: &str, actual: &str) -> Option<String> {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for number in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => return None,
+            (expected, actual) if expected != actual => {
+                return Some(format!(
+                    "line {}: expected {}, got {}",
+                    number,
+                    expected.map_or(String::from("end of transcript"), |l| format!("`{}`", l)),
+                    actual.map_or(String::from("end of transcript"), |l| format!("`{}`", l)),
+                ))
+            }
+            _ => (),
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Reader;
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn transcripts() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/transcripts");
+        let mut failures = Vec::new();
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "etop") {
+                continue;
+            }
+            let expected = fs::read_to_string(&path).unwrap();
+            let actual = replay(&expected, &Options::default()).unwrap();
+            if let Some(difference) = difference(&expected, &actual) {
+                failures.push(format!("{}: {}", path.display(), difference));
+            }
+        }
+        assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn record_then_replay() {
+        let path = std::env::temp_dir().join(format!("etop-transcript-{}", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "{}Calculator", HEADER).unwrap();
+        let mut input = Recording::new(
+            Reader::new("+ 1 2\nprint * 3\n4\n/ 1 0\n".as_bytes()),
+            file.try_clone().unwrap(),
+        );
+        let mut out = Tee::new(Vec::new(), file);
+        super::super::repl(
+            &mut input,
+            &mut out,
+            None,
+            "Calculator",
+            &Options::default(),
+        )
+        .unwrap();
+
+        let recorded = fs::read_to_string(&path).unwrap();
+        assert!(recorded.contains("etop # print * 3\n...  4\n12\n"));
+        assert_eq!(
+            difference(&recorded, &replay(&recorded, &Options::default()).unwrap()),
+            None
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reports_first_difference() {
+        assert_eq!(difference("a\nb\n", "a\nb"), None);
+        assert_eq!(
+            difference("a\nb\nc", "a\nd\nc"),
+            Some(String::from("line 2: expected `b`, got `d`"))
+        );
+        assert_eq!(
+            difference("a", "a\nb"),
+            Some(String::from("line 2: expected end of transcript, got `b`"))
+        );
+    }
+
+    #[test]
+    fn needs_a_language() {
+        assert!(replay("etop # + 1 2\n- : 3\n", &Options::default()).is_err());
+    }
+}
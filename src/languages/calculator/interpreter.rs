@@ -1,40 +1,206 @@
 use super::super::error::interpreter::{Error, Result};
-use super::Expr;
-
-pub fn interpreter(ast: Expr) -> Result<i32> {
-    match ast {
-        Expr::Add((e1, e2)) => {
-            let e1 = interpreter(*e1)?;
-            let e2 = interpreter(*e2)?;
-            Ok(e1 + e2)
+use super::super::io::{InterpreterIo, Output};
+use super::super::span::Spanned;
+use super::{Expr, Node, Program, Stmt};
+use std::io::Write;
+
+/// Runs each statement in order. `print` statements write their value to `out`;
+/// with `echo` set, so does every other statement.
+pub fn run_program(program: Program, echo: bool, out: &mut dyn Write) -> Result<()> {
+    run_program_with(program, echo, Numeric::default(), &mut Output(out))
+}
+
+/// Like [`run_program`], with arithmetic in the given mode and reading and
+/// writing through `io`.
+pub fn run_program_with(
+    program: Program,
+    echo: bool,
+    numeric: Numeric,
+    io: &mut dyn InterpreterIo,
+) -> Result<()> {
+    for stmt in program {
+        if let Some(value) = statement_with(stmt, numeric, io)? {
+            if echo {
+                io.write_str(&format!("{}\n", value))
+                    .map_err(|e| Error::io(&e))?;
+            }
         }
-        Expr::Sub((e1, e2)) => {
-            let e1 = interpreter(*e1)?;
-            let e2 = interpreter(*e2)?;
-            Ok(e1 - e2)
+    }
+    Ok(())
+}
+
+/// What arithmetic does with a result too large for an `i32`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Numeric {
+    /// Fail with an overflow error
+    #[default]
+    Checked,
+    /// Wrap around in two's complement
+    Wrapping,
+    /// Clamp to `i32::MIN` or `i32::MAX`
+    Saturating,
+}
+
+impl Numeric {
+    // Picks whichever flavour of an operation this mode calls for; `None` is an
+    // overflow
+    fn apply(
+        self,
+        checked: fn(i32, i32) -> Option<i32>,
+        wrapping: fn(i32, i32) -> i32,
+        saturating: fn(i32, i32) -> i32,
+        e1: i32,
+        e2: i32,
+    ) -> Option<i32> {
+        match self {
+            Numeric::Checked => checked(e1, e2),
+            Numeric::Wrapping => Some(wrapping(e1, e2)),
+            Numeric::Saturating => Some(saturating(e1, e2)),
         }
-        Expr::Mult((e1, e2)) => {
-            let e1 = interpreter(*e1)?;
-            let e2 = interpreter(*e2)?;
-            Ok(e1 * e2)
+    }
+}
+
+/// Evaluates one statement. `print` writes its value to `out`, while an
+/// expression statement's value is handed back to the caller.
+pub fn statement(stmt: Spanned<Stmt>, out: &mut dyn Write) -> Result<Option<i32>> {
+    statement_with(stmt, Numeric::default(), &mut Output(out))
+}
+
+/// Like [`statement`], with arithmetic in the given mode and writing through
+/// `io`.
+pub fn statement_with(
+    stmt: Spanned<Stmt>,
+    numeric: Numeric,
+    io: &mut dyn InterpreterIo,
+) -> Result<Option<i32>> {
+    match stmt.node {
+        Stmt::Expr(e) => Ok(Some(interpreter_with(e, numeric)?)),
+        Stmt::Print(e) => {
+            let value = interpreter_with(e, numeric)?;
+            io.write_str(&format!("{}\n", value))
+                .map_err(|e| Error::io(&e).with_span(stmt.span))?;
+            Ok(None)
         }
-        Expr::Div((e1, e2)) => {
-            let e1 = interpreter(*e1)?;
-            let e2 = interpreter(*e2)?;
-            if e2 == 0 {
-                Err(Error::div_by_zero())
-            } else {
-                Ok(e1 / e2)
-            }
+    }
+}
+
+pub fn interpreter(ast: Node) -> Result<i32> {
+    interpreter_with(ast, Numeric::default())
+}
+
+/// Like [`interpreter`], with arithmetic in the given mode.
+pub fn interpreter_with(ast: Node, numeric: Numeric) -> Result<i32> {
+    evaluate(&ast, numeric)
+}
+
+fn evaluate(ast: &Node, numeric: Numeric) -> Result<i32> {
+    match &ast.node {
+        Expr::Add((e1, e2)) | Expr::Sub((e1, e2)) | Expr::Mult((e1, e2)) | Expr::Div((e1, e2)) => {
+            let e1 = evaluate(e1, numeric)?;
+            let e2 = evaluate(e2, numeric)?;
+            operate(ast, e1, e2, numeric)
+        }
+        Expr::Int(i) => Ok(*i),
+        Expr::Error => Err(Error::error_node().with_span(ast.span)),
+    }
+}
+
+/// The last step of evaluating `node`: applies its operator to the values of
+/// its operands, `e1` and `e2`, once they are known. Errors are located at
+/// `node`. A node without operands is simply evaluated.
+pub fn operate(node: &Node, e1: i32, e2: i32, numeric: Numeric) -> Result<i32> {
+    let result = match node.node {
+        Expr::Add(_) => numeric.apply(
+            i32::checked_add,
+            i32::wrapping_add,
+            i32::saturating_add,
+            e1,
+            e2,
+        ),
+        Expr::Sub(_) => numeric.apply(
+            i32::checked_sub,
+            i32::wrapping_sub,
+            i32::saturating_sub,
+            e1,
+            e2,
+        ),
+        Expr::Mult(_) => numeric.apply(
+            i32::checked_mul,
+            i32::wrapping_mul,
+            i32::saturating_mul,
+            e1,
+            e2,
+        ),
+        Expr::Div(_) if e2 == 0 => return Err(Error::div_by_zero().with_span(node.span)),
+        // Only `i32::MIN / -1` can overflow
+        Expr::Div(_) => numeric.apply(
+            i32::checked_div,
+            i32::wrapping_div,
+            i32::saturating_div,
+            e1,
+            e2,
+        ),
+        Expr::Int(_) | Expr::Error => return evaluate(node, numeric),
+    };
+    result.ok_or_else(|| Error::overflow().with_span(node.span))
+}
+
+/// Evaluates an expression one reduction at a time, leftmost innermost first,
+/// returning every form it takes along the way: `+ 5 * 4 3`, then `+ 5 12`,
+/// then `17`. The first step is `ast` itself and the last is its value.
+pub fn reductions(ast: Node) -> Result<Vec<Node>> {
+    reductions_with(ast, Numeric::default())
+}
+
+/// Like [`reductions`], with arithmetic in the given mode.
+pub fn reductions_with(ast: Node, numeric: Numeric) -> Result<Vec<Node>> {
+    let mut steps = vec![ast];
+    loop {
+        let last = steps.last().expect("steps start with the expression");
+        if let Expr::Int(_) = last.node {
+            return Ok(steps);
         }
-        Expr::Int(i) => Ok(i),
+        let next = reduce(last, numeric)?;
+        steps.push(next);
     }
 }
 
+// Performs the leftmost reduction in `node`, which is not yet a value
+fn reduce(node: &Node, numeric: Numeric) -> Result<Node> {
+    let rebuild = |e1: Node, e2: Node| -> Expr {
+        let operands = (Box::new(e1), Box::new(e2));
+        match node.node {
+            Expr::Add(_) => Expr::Add(operands),
+            Expr::Sub(_) => Expr::Sub(operands),
+            Expr::Mult(_) => Expr::Mult(operands),
+            _ => Expr::Div(operands),
+        }
+    };
+    let reduced = match &node.node {
+        Expr::Add((e1, e2)) | Expr::Sub((e1, e2)) | Expr::Mult((e1, e2)) | Expr::Div((e1, e2)) => {
+            match (&e1.node, &e2.node) {
+                (Expr::Int(e1), Expr::Int(e2)) => Expr::Int(operate(node, *e1, *e2, numeric)?),
+                (Expr::Int(_), _) => rebuild((**e1).clone(), reduce(e2, numeric)?),
+                _ => rebuild(reduce(e1, numeric)?, (**e2).clone()),
+            }
+        }
+        // Only an `Error` node is left, which fails to evaluate
+        _ => Expr::Int(evaluate(node, numeric)?),
+    };
+    Ok(Spanned {
+        node: reduced,
+        span: node.span,
+    })
+}
+
 #[cfg(test)]
 mod tests {
+    use super::super::super::io::Buffer;
+    use super::super::super::span::Span;
     use super::super::lexer;
     use super::super::parser;
+    use super::super::pretty;
+    use super::super::{run, run_with, CalculatorError};
     use super::*;
 
     #[test]
@@ -117,6 +283,125 @@ mod tests {
         )
     }
 
+    #[test]
+    fn div_by_zero_span() {
+        let input = "+ 1 / 4 0";
+        let err =
+            interpreter(parser::parser(lexer::lexer(input, 0).unwrap()).unwrap()).unwrap_err();
+        assert_eq!(err.span(), Some(Span::new(input, 4, 9)))
+    }
+
+    #[test]
+    fn div_by_zero_message() {
+        use std::error::Error as StdError;
+
+        let err = CalculatorError::Interpreter(
+            interpreter(parser::parser(lexer::lexer("/ 4 0", 0).unwrap()).unwrap()).unwrap_err(),
+        );
+        assert_eq!(err.code(), "I001");
+        assert_eq!(
+            err.to_string(),
+            "Interpreter error [I001]: division by zero"
+        );
+        assert_eq!(err.source().unwrap().to_string(), "division by zero");
+    }
+
+    #[test]
+    fn program_output() {
+        let mut out = Vec::new();
+        run("print 1; 2\n# comment\n* 3 4 ;; product", false, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "1\n");
+
+        let mut out = Vec::new();
+        run("print 1; 2\n# comment\n* 3 4 ;; product", true, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "1\n2\n12\n");
+    }
+
+    #[test]
+    fn program_stops_at_error() {
+        let mut out = Vec::new();
+        let err = run("print 1\nprint / 1 0\nprint 3", false, &mut out).unwrap_err();
+        assert_eq!(err.code(), "I001");
+        assert_eq!(String::from_utf8(out).unwrap(), "1\n");
+    }
+
+    #[test]
+    fn program_modes() {
+        let source = "print + 2147483647 1";
+        let mut io = Buffer::new("");
+        run_with(source, false, Numeric::Wrapping, &mut io).unwrap();
+        assert_eq!(io.output(), format!("{}\n", i32::MIN));
+
+        let mut io = Buffer::new("");
+        let err = run_with(source, false, Numeric::Checked, &mut io).unwrap_err();
+        assert_eq!(err.code(), "I005");
+    }
+
+    #[test]
+    fn overflow_modes() {
+        let eval = |input: &str, numeric| {
+            interpreter_with(
+                parser::parser(lexer::lexer(input, 0).unwrap()).unwrap(),
+                numeric,
+            )
+        };
+        let input = "+ 1 * 65536 32768";
+        let err = eval(input, Numeric::Checked).unwrap_err();
+        assert_eq!(err.code(), "I005");
+        assert_eq!(err.span(), Some(Span::new(input, 4, 17)));
+        assert_eq!(eval(input, Numeric::Wrapping).unwrap(), i32::MIN + 1);
+        assert_eq!(eval(input, Numeric::Saturating).unwrap(), i32::MAX);
+        assert_eq!(
+            eval("- 0 * 65536 65536", Numeric::Saturating).unwrap(),
+            -i32::MAX
+        );
+    }
+
+    #[test]
+    fn reduction_steps() {
+        let parse = |input: &str| parser::parser(lexer::lexer(input, 0).unwrap()).unwrap();
+        let steps: Vec<String> = reductions(parse("+ 5 * 4 3"))
+            .unwrap()
+            .iter()
+            .map(pretty::expr)
+            .collect();
+        assert_eq!(steps, vec!["+ 5 * 4 3", "+ 5 12", "17"]);
+
+        let steps: Vec<String> = reductions(parse("- * 1 2 / 9 3"))
+            .unwrap()
+            .iter()
+            .map(pretty::expr)
+            .collect();
+        assert_eq!(steps, vec!["- * 1 2 / 9 3", "- 2 / 9 3", "- 2 3", "- 0 1"]);
+    }
+
+    #[test]
+    fn reduction_steps_are_source() {
+        let parse = |input: &str| parser::parser(lexer::lexer(input, 0).unwrap()).unwrap();
+        let min = format!("- - 0 {} 1", i32::MAX);
+        for input in ["+ - 2 3 5", "* - 1 4 - 0 7", "/ - 3 9 2", min.as_str()] {
+            let value = interpreter(parse(input)).unwrap();
+            for step in reductions(parse(input)).unwrap() {
+                let source = pretty::expr(&step);
+                assert_eq!(interpreter(parse(&source)).unwrap(), value, "{}", source);
+            }
+        }
+        let steps: Vec<String> = reductions(parse("+ - 2 3 5"))
+            .unwrap()
+            .iter()
+            .map(pretty::expr)
+            .collect();
+        assert_eq!(steps, vec!["+ - 2 3 5", "+ - 0 1 5", "4"]);
+    }
+
+    #[test]
+    fn reduction_failure() {
+        let input = "+ * 2 3 / 1 0";
+        let err = reductions(parser::parser(lexer::lexer(input, 0).unwrap()).unwrap()).unwrap_err();
+        assert_eq!(err.code(), "I001");
+        assert_eq!(err.span(), Some(Span::new(input, 8, 13)));
+    }
+
     #[test]
     fn mixed_basic_nested() {
         assert_eq!(
@@ -1,67 +1,43 @@
 use super::super::error::lexer::{Error, Result};
-use super::Tok;
-use regex::Regex;
+use super::super::lexer::{Builder, Lexer};
+use super::{Tok, Token};
+use std::sync::OnceLock;
 
-pub fn lexer(input: &str, pos: usize) -> Result<Vec<Tok>, String> {
-    match (input, pos) {
-        (_, pos) if pos >= input.len() => {
-            let tokens: Vec<Tok> = Vec::new();
-            Ok(tokens)
-        }
-        (input, pos) if Regex::new(r"^\+").unwrap().is_match(&input[pos..]) => {
-            let mut tokens: Vec<Tok> = lexer(input, pos + 1)?;
-            tokens.insert(0, Tok::TokAdd);
-            Ok(tokens)
-        }
-        (input, pos) if Regex::new(r"^\*").unwrap().is_match(&input[pos..]) => {
-            let mut tokens: Vec<Tok> = lexer(input, pos + 1)?;
-            tokens.insert(0, Tok::TokMult);
-            Ok(tokens)
-        }
-        (input, pos) if Regex::new(r"^\-").unwrap().is_match(&input[pos..]) => {
-            let mut tokens: Vec<Tok> = lexer(input, pos + 1)?;
-            tokens.insert(0, Tok::TokSub);
-            Ok(tokens)
-        }
-        (input, pos) if Regex::new(r"^\/").unwrap().is_match(&input[pos..]) => {
-            let mut tokens: Vec<Tok> = lexer(input, pos + 1)?;
-            tokens.insert(0, Tok::TokDiv);
-            Ok(tokens)
-        }
-        _ => {
-            let re_whitespace = Regex::new(r"^(\s+)").unwrap();
-            let re_numbers = Regex::new(r"^([0-9]+)").unwrap();
-            if let Some(captures) = re_whitespace.captures(&input[pos..]) {
-                if let Some(matched) = captures.get(0) {
-                    let matched_str = matched.as_str();
-                    Ok(lexer(input, pos + matched_str.len())?)
-                } else {
-                    Err(Error::invalid_input(input.to_string()))
-                }
-            } else if let Some(captures) = re_numbers.captures(&input[pos..]) {
-                if let Some(matched) = captures.get(0) {
-                    let matched_str = matched.as_str();
+/// Splits `input` from byte `pos` onwards into tokens.
+pub fn lexer(input: &str, pos: usize) -> Result<Vec<Token>, String> {
+    calculator().lex(input, pos)
+}
 
-                    match matched_str.parse::<i32>() {
-                        Ok(number) => {
-                            let mut more_tokens = lexer(input, pos + matched_str.len())?;
-                            more_tokens.insert(0, Tok::TokInt(number));
-                            Ok(more_tokens)
-                        }
-                        Err(_) => Err(Error::invalid_input(input.to_string())),
-                    }
-                } else {
-                    Err(Error::invalid_input(input.to_string()))
-                }
-            } else {
-                Err(Error::invalid_input(input.to_string()))
-            }
-        }
-    }
+/// Like [`lexer`], but on failure also hands back the tokens before the error.
+pub fn lexer_partial(input: &str, pos: usize) -> (Vec<Token>, Option<Error<String>>) {
+    calculator().lex_partial(input, pos)
+}
+
+fn calculator() -> &'static Lexer<Tok> {
+    static LEXER: OnceLock<Lexer<Tok>> = OnceLock::new();
+    LEXER.get_or_init(|| {
+        Builder::new()
+            // Line comments run up to, but not including, the newline
+            .skip(r"(#|;;)[^\n]*")
+            .token(";", |_| Some(Tok::TokSemi))
+            // Newlines separate statements, so they are tokens rather than
+            // whitespace
+            .token(r"\n", |_| Some(Tok::TokNewline))
+            .skip(r"[^\S\n]+")
+            .token(r"print\b", |_| Some(Tok::TokPrint))
+            .token(r"\+", |_| Some(Tok::TokAdd))
+            .token(r"\*", |_| Some(Tok::TokMult))
+            .token("-", |_| Some(Tok::TokSub))
+            .token("/", |_| Some(Tok::TokDiv))
+            .token("[0-9]+", |text| text.parse().ok().map(Tok::TokInt))
+            .build()
+            .expect("the calculator's patterns are valid")
+    })
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::super::span::Span;
     use super::*;
 
     #[test]
@@ -69,9 +45,60 @@ mod tests {
         assert!(lexer(&String::from("^ 9 8"), 0).is_err())
     }
 
+    #[test]
+    fn invalid_tokens_span() {
+        let err = lexer(&String::from("+ 9 ^ 8"), 0).unwrap_err();
+        assert_eq!(err.span(), Some(Span::new("+ 9 ^ 8", 4, 5)));
+    }
+
+    #[test]
+    fn invalid_tokens_message() {
+        let err = lexer(&String::from("+ 9 ^ 8"), 0).unwrap_err();
+        assert_eq!(err.code(), "L001");
+        assert_eq!(err.to_string(), "unrecognised input `^`");
+    }
+
+    #[test]
+    fn token_spans() {
+        let spans: Vec<Span> = lexer(&String::from("+ 12\n3"), 0)
+            .unwrap()
+            .iter()
+            .map(|tok| tok.span)
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                Span {
+                    start: 0,
+                    end: 1,
+                    line: 1,
+                    col: 1
+                },
+                Span {
+                    start: 2,
+                    end: 4,
+                    line: 1,
+                    col: 3
+                },
+                Span {
+                    start: 4,
+                    end: 5,
+                    line: 1,
+                    col: 5
+                },
+                Span {
+                    start: 5,
+                    end: 6,
+                    line: 2,
+                    col: 1
+                },
+            ]
+        );
+    }
+
     #[test]
     fn nothing() {
-        assert_eq!(lexer(&String::from(""), 0).unwrap(), Vec::new());
+        assert_eq!(lexer("", 0).unwrap(), Vec::<Tok>::new());
     }
 
     #[test]
@@ -112,6 +139,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn statements() {
+        assert_eq!(
+            lexer(&String::from("print 5; 4\n\t3"), 0).unwrap(),
+            vec![
+                Tok::TokPrint,
+                Tok::TokInt(5),
+                Tok::TokSemi,
+                Tok::TokInt(4),
+                Tok::TokNewline,
+                Tok::TokInt(3)
+            ]
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            lexer(&String::from("# header\n+ 1 2 ;; sum\nprinter"), 0).map_err(|err| err.span()),
+            Err(Some(Span::new("# header\n+ 1 2 ;; sum\nprinter", 22, 23)))
+        );
+        assert_eq!(
+            lexer(&String::from("# header\n+ 1 2 ;; sum; 3\n4 # four"), 0).unwrap(),
+            vec![
+                Tok::TokNewline,
+                Tok::TokAdd,
+                Tok::TokInt(1),
+                Tok::TokInt(2),
+                Tok::TokNewline,
+                Tok::TokInt(4)
+            ]
+        );
+    }
+
     #[test]
     fn numbers_operators() {
         assert_eq!(
@@ -1,8 +1,16 @@
 pub mod interpreter;
 pub mod lexer;
 pub mod parser;
+pub mod pretty;
+pub mod serial;
+use super::diagnostic::Diagnostic;
 use super::error;
+use super::io::{InterpreterIo, Output};
+use super::json::Value;
+use super::span::{Span, Spanned};
+use interpreter::Numeric;
 use std::fmt;
+use std::io::Write;
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Tok {
@@ -11,32 +19,140 @@ pub enum Tok {
     TokMult,
     TokDiv,
     TokInt(i32),
+    TokPrint,
+    TokSemi,
+    TokNewline,
 }
 
-#[derive(PartialEq, Debug)]
+pub type Token = Spanned<Tok>;
+
+impl fmt::Display for Tok {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Tok::TokAdd => write!(f, "+"),
+            Tok::TokSub => write!(f, "-"),
+            Tok::TokMult => write!(f, "*"),
+            Tok::TokDiv => write!(f, "/"),
+            Tok::TokInt(i) => write!(f, "{}", i),
+            Tok::TokPrint => write!(f, "print"),
+            Tok::TokSemi => write!(f, ";"),
+            Tok::TokNewline => write!(f, "newline"),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
 pub enum Expr {
-    Add((Box<Expr>, Box<Expr>)),
-    Sub((Box<Expr>, Box<Expr>)),
-    Mult((Box<Expr>, Box<Expr>)),
-    Div((Box<Expr>, Box<Expr>)),
+    Add((Box<Node>, Box<Node>)),
+    Sub((Box<Node>, Box<Node>)),
+    Mult((Box<Node>, Box<Node>)),
+    Div((Box<Node>, Box<Node>)),
     Int(i32),
+    /// Stands in for input the parser could not make sense of when recovering
+    /// from errors
+    Error,
 }
 
+pub type Node = Spanned<Expr>;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Stmt {
+    Expr(Node),
+    Print(Node),
+}
+
+pub type Program = Vec<Spanned<Stmt>>;
+
 #[derive(Debug)]
 pub enum CalculatorError {
     Parser(error::parser::Error<Tok, Vec<Tok>>),
     Lexer(error::lexer::Error<String>),
     Interpreter(error::interpreter::Error),
+    Decode(error::decode::Error),
 }
 
-impl<'a> std::error::Error for CalculatorError {}
+/// Runs a whole program, e.g. the contents of a `.calc` file. `print` statements
+/// write their value to `out`; with `echo` set, so does every other statement.
+pub fn run(source: &str, echo: bool, out: &mut dyn Write) -> Result<(), CalculatorError> {
+    run_with(source, echo, Numeric::default(), &mut Output(out))
+}
 
-impl<'a> fmt::Display for CalculatorError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+/// Like [`run`], with arithmetic in the given mode and reading and writing
+/// through `io`.
+pub fn run_with(
+    source: &str,
+    echo: bool,
+    numeric: Numeric,
+    io: &mut dyn InterpreterIo,
+) -> Result<(), CalculatorError> {
+    let lexed = lexer::lexer(source, 0).map_err(CalculatorError::Lexer)?;
+    let parsed = parser::program(lexed).map_err(CalculatorError::Parser)?;
+    interpreter::run_program_with(parsed, echo, numeric, io).map_err(CalculatorError::Interpreter)
+}
+
+impl CalculatorError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CalculatorError::Parser(err) => err.code(),
+            CalculatorError::Lexer(err) => err.code(),
+            CalculatorError::Interpreter(err) => err.code(),
+            CalculatorError::Decode(err) => err.code(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        Diagnostic::from(self).to_json()
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CalculatorError::Parser(err) => err.span(),
+            CalculatorError::Lexer(err) => err.span(),
+            CalculatorError::Interpreter(err) => err.span(),
+            CalculatorError::Decode(err) => err.span(),
+        }
+    }
+}
+
+impl std::error::Error for CalculatorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            CalculatorError::Parser(err) => write!(f, "Parser error: {:?}", err),
-            CalculatorError::Lexer(err) => write!(f, "Lexer error: {:?}", err),
-            CalculatorError::Interpreter(err) => write!(f, "Interpreter error: {:?}", err),
+            CalculatorError::Parser(err) => Some(err),
+            CalculatorError::Lexer(err) => Some(err),
+            CalculatorError::Interpreter(err) => Some(err),
+            CalculatorError::Decode(err) => Some(err),
         }
     }
 }
+
+// The phase's diagnostic, with advice that only applies to the calculator
+impl From<&CalculatorError> for Diagnostic {
+    fn from(err: &CalculatorError) -> Self {
+        match err {
+            CalculatorError::Parser(err) => {
+                let diagnostic = Diagnostic::from(err);
+                match (err.code(), err.tok_list().and_then(|toks| toks.first())) {
+                    ("P004", _) => diagnostic.with_help(
+                        "a statement is a single expression; separate statements with `;` or a new line",
+                    ),
+                    ("P005", Some(Tok::TokPrint)) => {
+                        diagnostic.with_help("`print` can only begin a statement")
+                    }
+                    _ => diagnostic,
+                }
+            }
+            CalculatorError::Lexer(err) => Diagnostic::from(err),
+            CalculatorError::Interpreter(err) => match err.code() {
+                "I005" => Diagnostic::from(err).with_note("numbers are 32-bit signed integers"),
+                _ => Diagnostic::from(err),
+            },
+            CalculatorError::Decode(err) => Diagnostic::from(err),
+        }
+    }
+}
+
+impl fmt::Display for CalculatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Diagnostic::from(self))
+    }
+}
@@ -1,79 +1,240 @@
 use super::super::error::parser::{Error, Result};
-use super::{Expr, Tok};
+use super::super::span::{Span, Spanned};
+use super::{Expr, Node, Program, Stmt, Tok, Token};
+
+/// The outcome of parsing with error recovery: a best-effort AST in which
+/// unparseable regions are `Expr::Error` nodes, plus every error encountered in
+/// source order.
+#[derive(Debug)]
+pub struct Recovered<T> {
+    pub ast: T,
+    pub errors: Vec<Error<Tok, Vec<Tok>>>,
+}
+
+/// The grammar of [`program`] in the form [`Grammar`] reads, which needs it to
+/// be LL(1). Integers are the terminal `int`. Statements may be empty, as
+/// around blank lines and comments.
+///
+/// [`Grammar`]: super::super::grammar::Grammar
+pub const GRAMMAR: &str = "\
+P  -> S Ps | Ps
+Ps -> ; P | newline P | ε
+S  -> print E | E
+E  -> + E E | - E E | * E E | / E E | int
+";
 
 /** Grammar:
+P -> S ; P | S \n P | S | ε
+S -> print E | E
 E -> + N E | - N E | * N E | / N E | N
 N -> -2,147,483,648 | -2,147,483,647 | -2,147,483,646 | ... | 2,147,483,647
 */
-pub fn parser(toks: Vec<Tok>) -> Result<Expr, Tok, Vec<Tok>> {
-    let (toks, e) = parse_e(toks)?;
-    if !toks.is_empty() {
-        Err(Error::tokens_not_empty(toks))
+pub fn program(toks: Vec<Token>) -> Result<Program, Tok, Vec<Tok>> {
+    let Recovered { ast, mut errors } = program_with_recovery(toks);
+    if errors.is_empty() {
+        Ok(ast)
     } else {
-        Ok(e)
+        Err(errors.remove(0))
     }
 }
 
-fn parse_e(toks: Vec<Tok>) -> Result<(Vec<Tok>, Expr), Tok, Vec<Tok>> {
-    if let Some(tok_head) = toks.get(0) {
-        match tok_head {
-            Tok::TokAdd => {
-                let toks = match_token(&toks, Tok::TokAdd)?;
-                let (toks, e1) = parse_e(toks)?;
-                let (toks, e2) = parse_e(toks)?;
-                Ok((toks, Expr::Add((Box::new(e1), Box::new(e2)))))
-            }
-            Tok::TokSub => {
-                let toks = match_token(&toks, Tok::TokSub)?;
-                let (toks, e1) = parse_e(toks)?;
-                let (toks, e2) = parse_e(toks)?;
-                Ok((toks, Expr::Sub((Box::new(e1), Box::new(e2)))))
-            }
-            Tok::TokMult => {
-                let toks = match_token(&toks, Tok::TokMult)?;
-                let (toks, e1) = parse_e(toks)?;
-                let (toks, e2) = parse_e(toks)?;
-                Ok((toks, Expr::Mult((Box::new(e1), Box::new(e2)))))
-            }
-            Tok::TokDiv => {
-                let toks = match_token(&toks, Tok::TokDiv)?;
-                let (toks, e1) = parse_e(toks)?;
-                let (toks, e2) = parse_e(toks)?;
-                Ok((toks, Expr::Div((Box::new(e1), Box::new(e2)))))
-            }
-            Tok::TokInt(_) => Ok(parse_n(toks)?),
+/// Parses a program, carrying on past errors. Statement separators are the
+/// synchronisation points: an error anywhere in a statement is reported and
+/// parsing resumes with the next statement.
+pub fn program_with_recovery(toks: Vec<Token>) -> Recovered<Program> {
+    let mut program = Vec::new();
+    let mut errors = Vec::new();
+    let mut rest = &toks[..];
+    while !rest.is_empty() {
+        let end = rest
+            .iter()
+            .position(|tok| matches!(tok.node, Tok::TokSemi | Tok::TokNewline))
+            .unwrap_or(rest.len());
+        let (stmt_toks, tail) = rest.split_at(end);
+        // A statement cut short ends where its separator begins
+        let eof = match (tail.first(), stmt_toks.last()) {
+            (Some(sep), _) => Span {
+                end: sep.span.start,
+                ..sep.span
+            },
+            (None, Some(last)) => last.span.after(),
+            (None, None) => Span::default(),
+        };
+        if let Some(stmt) = parse_s(stmt_toks, eof, &mut errors) {
+            program.push(stmt);
+        }
+        rest = tail.get(1..).unwrap_or_default();
+    }
+    Recovered {
+        ast: program,
+        errors,
+    }
+}
+
+fn parse_s(
+    toks: &[Token],
+    eof: Span,
+    errors: &mut Vec<Error<Tok, Vec<Tok>>>,
+) -> Option<Spanned<Stmt>> {
+    let (first, last) = (toks.first()?, toks.last()?);
+    let span = first.span.to(last.span);
+    let Recovered {
+        ast,
+        errors: stmt_errors,
+    } = if first.node == Tok::TokPrint {
+        let recovered = parse_top_e(toks[1..].to_vec(), eof);
+        Recovered {
+            ast: Stmt::Print(recovered.ast),
+            errors: recovered.errors,
         }
     } else {
-        Err(Error::tokens_empty())
+        let recovered = parse_top_e(toks.to_vec(), eof);
+        Recovered {
+            ast: Stmt::Expr(recovered.ast),
+            errors: recovered.errors,
+        }
+    };
+    errors.extend(stmt_errors);
+    Some(Spanned::new(ast, span))
+}
+
+pub fn parser(toks: Vec<Token>) -> Result<Node, Tok, Vec<Tok>> {
+    let Recovered { ast, mut errors } = parser_with_recovery(toks);
+    if errors.is_empty() {
+        Ok(ast)
+    } else {
+        Err(errors.remove(0))
+    }
+}
+
+/// Parses like `parser` but carries on past errors. A missing operand becomes an
+/// `Expr::Error` node and input left over after the expression is reported and
+/// skipped.
+pub fn parser_with_recovery(toks: Vec<Token>) -> Recovered<Node> {
+    // Errors raised on running out of tokens have nowhere to point but the end
+    let eof = toks
+        .last()
+        .map_or(Span::new("", 0, 0), |tok| tok.span.after());
+    parse_top_e(toks, eof)
+}
+
+// An expression that should use up all of `toks`
+fn parse_top_e(toks: Vec<Token>, eof: Span) -> Recovered<Node> {
+    let mut errors = Vec::new();
+    let (toks, ast) = parse_e(toks, eof, &mut errors);
+    if let (Some(first), Some(last)) = (toks.first(), toks.last()) {
+        let span = first.span.to(last.span);
+        errors.push(Error::tokens_not_empty(unspanned(&toks)).with_span(span));
     }
+    Recovered { ast, errors }
+}
+
+fn parse_e(
+    toks: Vec<Token>,
+    eof: Span,
+    errors: &mut Vec<Error<Tok, Vec<Tok>>>,
+) -> (Vec<Token>, Node) {
+    let tok_head = match toks.first() {
+        Some(tok_head) => *tok_head,
+        None => {
+            // Every operand still owed past the end is missing for the same
+            // reason, so only the first one is worth reporting
+            if !errors.iter().any(|err| err.is_tokens_empty()) {
+                errors.push(Error::tokens_empty().with_span(eof));
+            }
+            return (toks, Spanned::new(Expr::Error, eof));
+        }
+    };
+    let expr: fn((Box<Node>, Box<Node>)) -> Expr = match tok_head.node {
+        Tok::TokAdd => Expr::Add,
+        Tok::TokSub => Expr::Sub,
+        Tok::TokMult => Expr::Mult,
+        Tok::TokDiv => Expr::Div,
+        Tok::TokPrint | Tok::TokSemi | Tok::TokNewline => {
+            errors.push(
+                Error::production_rule_failure(String::from("E rule"), unspanned(&toks))
+                    .with_span(tok_head.span),
+            );
+            return (toks[1..].to_vec(), Spanned::new(Expr::Error, tok_head.span));
+        }
+        Tok::TokInt(_) => {
+            return match parse_n(toks.clone()) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    errors.push(err);
+                    (toks[1..].to_vec(), Spanned::new(Expr::Error, tok_head.span))
+                }
+            }
+        }
+    };
+    let toks = match match_token(&toks, tok_head.node) {
+        Ok(toks) => toks,
+        Err(err) => {
+            errors.push(err);
+            toks[1..].to_vec()
+        }
+    };
+    let (toks, e1) = parse_e(toks, eof, errors);
+    let (toks, e2) = parse_e(toks, eof, errors);
+    let span = tok_head.span.to(e2.span);
+    (toks, Spanned::new(expr((Box::new(e1), Box::new(e2))), span))
 }
 
-fn parse_n(toks: Vec<Tok>) -> Result<(Vec<Tok>, Expr), Tok, Vec<Tok>> {
-    match toks.get(0).unwrap() {
+fn parse_n(toks: Vec<Token>) -> Result<(Vec<Token>, Node), Tok, Vec<Tok>> {
+    let tok_head = *toks.first().unwrap();
+    match tok_head.node {
         Tok::TokInt(i) => {
-            let toks = match_token(&toks, Tok::TokInt(*i))?;
-            Ok((toks, Expr::Int(*i)))
+            let toks = match_token(&toks, Tok::TokInt(i))?;
+            Ok((toks, Spanned::new(Expr::Int(i), tok_head.span)))
         }
-        _ => Err(Error::production_rule_failure(String::from("N rule"), toks)),
+        _ => Err(
+            Error::production_rule_failure(String::from("N rule"), unspanned(&toks))
+                .with_span(tok_head.span),
+        ),
     }
 }
 
-fn match_token(toks: &[Tok], tok: Tok) -> Result<Vec<Tok>, Tok, Vec<Tok>> {
+fn match_token(toks: &[Token], tok: Tok) -> Result<Vec<Token>, Tok, Vec<Tok>> {
     match toks.split_first() {
         None => Err(Error::invalid_input(tok)),
-        Some((h, t)) if *h == tok => Ok(t.to_vec()),
-        Some((h, _)) => Err(Error::mismatched_token(tok, toks.to_vec(), *h)),
+        Some((h, t)) if h.node == tok => Ok(t.to_vec()),
+        Some((h, _)) => {
+            Err(Error::mismatched_token(tok, unspanned(toks), h.node).with_span(h.span))
+        }
     }
 }
 
+fn unspanned(toks: &[Token]) -> Vec<Tok> {
+    toks.iter().map(|tok| tok.node).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::lexer;
     use super::*;
 
+    // Spans for single-line test inputs
+    fn span(start: usize, end: usize) -> Span {
+        Span {
+            start,
+            end,
+            line: 1,
+            col: start + 1,
+        }
+    }
+
+    fn int(i: i32, start: usize) -> Node {
+        Spanned::new(Expr::Int(i), span(start, start + i.to_string().len()))
+    }
+
+    fn op(expr: fn((Box<Node>, Box<Node>)) -> Expr, start: usize, e1: Node, e2: Node) -> Node {
+        let span = span(start, e2.span.end);
+        Spanned::new(expr((Box::new(e1), Box::new(e2))), span)
+    }
+
     #[test]
     fn nothing() {
-        assert!(parser(lexer::lexer(&String::from(""), 0).unwrap()).is_err());
+        assert!(parser(lexer::lexer("", 0).unwrap()).is_err());
     }
 
     #[test]
@@ -81,11 +242,130 @@ mod tests {
         assert!(parser(lexer::lexer(&String::from("5 + 8 + 9 8"), 0).unwrap()).is_err());
     }
 
+    #[test]
+    fn tokens_empty_span() {
+        let err = parser(lexer::lexer(&String::from("+ 5"), 0).unwrap()).unwrap_err();
+        assert_eq!(err.span(), Some(span(3, 3)));
+    }
+
+    #[test]
+    fn tokens_empty_message() {
+        let err = parser(lexer::lexer(&String::from("+ 5"), 0).unwrap()).unwrap_err();
+        assert_eq!(err.code(), "P003");
+        assert_eq!(err.to_string(), "unexpected end of input");
+    }
+
+    #[test]
+    fn tokens_not_empty_span() {
+        let err = parser(lexer::lexer(&String::from("5 + 8 9"), 0).unwrap()).unwrap_err();
+        assert_eq!(err.span(), Some(span(2, 7)));
+    }
+
+    #[test]
+    fn tokens_not_empty_json() {
+        let err = parser(lexer::lexer(&String::from("5 + 8"), 0).unwrap()).unwrap_err();
+        assert_eq!(
+            err.to_json().to_string(),
+            concat!(
+                r#"{"phase":"parser","code":"P004","severity":"error","#,
+                r#""message":"unexpected input after the end of the expression","#,
+                r#""span":{"start":2,"end":5,"line":1,"col":3},"related":["+","8"],"#,
+                r#""notes":[],"help":null}"#
+            )
+        );
+    }
+
+    #[test]
+    fn recovery_missing_operands() {
+        let Recovered { ast, errors } =
+            parser_with_recovery(lexer::lexer(&String::from("+ 5 * 4"), 0).unwrap());
+        let missing = Spanned::new(Expr::Error, span(7, 7));
+        assert_eq!(
+            ast,
+            op(
+                Expr::Add,
+                0,
+                int(5, 2),
+                op(Expr::Mult, 4, int(4, 6), missing)
+            )
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code(), "P003");
+    }
+
+    #[test]
+    fn recovery_skips_leftover_input() {
+        let Recovered { ast, errors } =
+            parser_with_recovery(lexer::lexer(&String::from("- 9 + 1 2 3 4"), 0).unwrap());
+        assert_eq!(
+            ast,
+            op(
+                Expr::Sub,
+                0,
+                int(9, 2),
+                op(Expr::Add, 4, int(1, 6), int(2, 8))
+            )
+        );
+        let codes: Vec<&str> = errors.iter().map(|err| err.code()).collect();
+        assert_eq!(codes, vec!["P004"]);
+        assert_eq!(errors[0].span(), Some(span(10, 13)));
+
+        let Recovered { errors, .. } = parser_with_recovery(Vec::new());
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn print_in_expression() {
+        let err = parser(lexer::lexer(&String::from("+ 1 print 2"), 0).unwrap()).unwrap_err();
+        assert_eq!(err.code(), "P005");
+        assert_eq!(err.span(), Some(span(4, 9)));
+    }
+
+    #[test]
+    fn program_statements() {
+        let program =
+            program(lexer::lexer(&String::from("print + 5 4;\n\n3 # three"), 0).unwrap()).unwrap();
+        let stmts: Vec<Stmt> = program.into_iter().map(|stmt| stmt.node).collect();
+        assert_eq!(
+            stmts,
+            vec![
+                Stmt::Print(op(Expr::Add, 6, int(5, 8), int(4, 10))),
+                Stmt::Expr(Spanned::new(
+                    Expr::Int(3),
+                    Span {
+                        start: 14,
+                        end: 15,
+                        line: 3,
+                        col: 1
+                    }
+                ))
+            ]
+        );
+    }
+
+    #[test]
+    fn program_recovery_resynchronises() {
+        let input = "+ 1; print\n5 6 7; / 8 2";
+        let Recovered { ast, errors } = program_with_recovery(lexer::lexer(input, 0).unwrap());
+        assert_eq!(ast.len(), 4);
+        assert_eq!(ast[3].span, Span::new(input, 18, 23));
+        let found: Vec<(&str, Option<Span>)> =
+            errors.iter().map(|err| (err.code(), err.span())).collect();
+        assert_eq!(
+            found,
+            vec![
+                ("P003", Some(span(3, 3))),
+                ("P003", Some(span(10, 10))),
+                ("P004", Some(Span::new(input, 13, 16))),
+            ]
+        );
+    }
+
     #[test]
     fn add_basic() {
         assert_eq!(
             parser(lexer::lexer(&String::from("+ 5 4"), 0).unwrap()).unwrap(),
-            Expr::Add((Box::new(Expr::Int(5)), Box::new(Expr::Int(4))))
+            op(Expr::Add, 0, int(5, 2), int(4, 4))
         )
     }
 
@@ -93,10 +373,12 @@ mod tests {
     fn add_basic_nested() {
         assert_eq!(
             parser(lexer::lexer(&String::from("+ 5 + 4 3"), 0).unwrap()).unwrap(),
-            Expr::Add((
-                Box::new(Expr::Int(5)),
-                Box::new(Expr::Add((Box::new(Expr::Int(4)), Box::new(Expr::Int(3)))))
-            ))
+            op(
+                Expr::Add,
+                0,
+                int(5, 2),
+                op(Expr::Add, 4, int(4, 6), int(3, 8))
+            )
         )
     }
 
@@ -104,7 +386,7 @@ mod tests {
     fn sub_basic() {
         assert_eq!(
             parser(lexer::lexer(&String::from("- 5 4"), 0).unwrap()).unwrap(),
-            Expr::Sub((Box::new(Expr::Int(5)), Box::new(Expr::Int(4))))
+            op(Expr::Sub, 0, int(5, 2), int(4, 4))
         )
     }
 
@@ -112,10 +394,12 @@ mod tests {
     fn sub_basic_nested() {
         assert_eq!(
             parser(lexer::lexer(&String::from("- 5 - 4 3"), 0).unwrap()).unwrap(),
-            Expr::Sub((
-                Box::new(Expr::Int(5)),
-                Box::new(Expr::Sub((Box::new(Expr::Int(4)), Box::new(Expr::Int(3)))))
-            ))
+            op(
+                Expr::Sub,
+                0,
+                int(5, 2),
+                op(Expr::Sub, 4, int(4, 6), int(3, 8))
+            )
         )
     }
 
@@ -123,7 +407,7 @@ mod tests {
     fn mult_basic() {
         assert_eq!(
             parser(lexer::lexer(&String::from("* 5 4"), 0).unwrap()).unwrap(),
-            Expr::Mult((Box::new(Expr::Int(5)), Box::new(Expr::Int(4))))
+            op(Expr::Mult, 0, int(5, 2), int(4, 4))
         )
     }
 
@@ -131,10 +415,12 @@ mod tests {
     fn mult_basic_nested() {
         assert_eq!(
             parser(lexer::lexer(&String::from("* 5 * 4 3"), 0).unwrap()).unwrap(),
-            Expr::Mult((
-                Box::new(Expr::Int(5)),
-                Box::new(Expr::Mult((Box::new(Expr::Int(4)), Box::new(Expr::Int(3)))))
-            ))
+            op(
+                Expr::Mult,
+                0,
+                int(5, 2),
+                op(Expr::Mult, 4, int(4, 6), int(3, 8))
+            )
         )
     }
 
@@ -142,7 +428,7 @@ mod tests {
     fn div_basic() {
         assert_eq!(
             parser(lexer::lexer(&String::from("/ 5 4"), 0).unwrap()).unwrap(),
-            Expr::Div((Box::new(Expr::Int(5)), Box::new(Expr::Int(4))))
+            op(Expr::Div, 0, int(5, 2), int(4, 4))
         )
     }
 
@@ -150,10 +436,12 @@ mod tests {
     fn div_basic_nested() {
         assert_eq!(
             parser(lexer::lexer(&String::from("/ 5 / 4 3"), 0).unwrap()).unwrap(),
-            Expr::Div((
-                Box::new(Expr::Int(5)),
-                Box::new(Expr::Div((Box::new(Expr::Int(4)), Box::new(Expr::Int(3)))))
-            ))
+            op(
+                Expr::Div,
+                0,
+                int(5, 2),
+                op(Expr::Div, 4, int(4, 6), int(3, 8))
+            )
         )
     }
 
@@ -161,10 +449,12 @@ mod tests {
     fn mixed_basic_nested() {
         assert_eq!(
             parser(lexer::lexer(&String::from("- 5 / 4 3"), 0).unwrap()).unwrap(),
-            Expr::Sub((
-                Box::new(Expr::Int(5)),
-                Box::new(Expr::Div((Box::new(Expr::Int(4)), Box::new(Expr::Int(3)))))
-            ))
+            op(
+                Expr::Sub,
+                0,
+                int(5, 2),
+                op(Expr::Div, 4, int(4, 6), int(3, 8))
+            )
         )
     }
 }
@@ -0,0 +1,249 @@
+use super::super::span::Spanned;
+use super::interpreter::{self, Numeric};
+use super::{Expr, Node, Program, Stmt};
+
+/// Renders a program as an indented tree, one node per line with its position:
+///
+/// ```text
+/// Print @1:1
+/// └─ Add @1:7
+///    ├─ Int 5 @1:9
+///    └─ Int 4 @1:11
+/// ```
+pub fn tree(program: &Program) -> String {
+    let mut out = String::new();
+    for stmt in program {
+        tree_stmt(stmt, &mut out);
+    }
+    out
+}
+
+fn tree_stmt(stmt: &Spanned<Stmt>, out: &mut String) {
+    let (label, e) = match &stmt.node {
+        Stmt::Expr(e) => ("Expr", e),
+        Stmt::Print(e) => ("Print", e),
+    };
+    out.push_str(&format!("{} @{}\n", label, stmt.span));
+    tree_expr(e, "", true, out);
+}
+
+fn tree_expr(node: &Node, prefix: &str, last: bool, out: &mut String) {
+    let (label, children) = match &node.node {
+        Expr::Add((e1, e2)) => (String::from("Add"), vec![e1, e2]),
+        Expr::Sub((e1, e2)) => (String::from("Sub"), vec![e1, e2]),
+        Expr::Mult((e1, e2)) => (String::from("Mult"), vec![e1, e2]),
+        Expr::Div((e1, e2)) => (String::from("Div"), vec![e1, e2]),
+        Expr::Int(i) => (format!("Int {}", i), vec![]),
+        Expr::Error => (String::from("Error"), vec![]),
+    };
+    let branch = if last { "└─ " } else { "├─ " };
+    out.push_str(&format!("{}{}{} @{}\n", prefix, branch, label, node.span));
+
+    let prefix = format!("{}{}", prefix, if last { "   " } else { "│  " });
+    for (i, child) in children.iter().enumerate() {
+        tree_expr(child, &prefix, i + 1 == children.len(), out);
+    }
+}
+
+/// Renders a statement back as source in canonical layout, with tokens
+/// separated by single spaces, e.g. `print + 5 * 4 3`.
+pub fn statement(stmt: &Spanned<Stmt>) -> String {
+    match &stmt.node {
+        Stmt::Expr(e) => expr(e),
+        Stmt::Print(e) => format!("print {}", expr(e)),
+    }
+}
+
+/// Renders an expression back as source. An `Error` node, which has no source
+/// form, renders as `?`. A negative number, which only evaluation produces,
+/// renders as a subtraction from zero, e.g. `- 0 1`, since `-1` would lex as
+/// the operator followed by `1`.
+pub fn expr(node: &Node) -> String {
+    let (op, e1, e2) = match &node.node {
+        Expr::Add((e1, e2)) => ("+", e1, e2),
+        Expr::Sub((e1, e2)) => ("-", e1, e2),
+        Expr::Mult((e1, e2)) => ("*", e1, e2),
+        Expr::Div((e1, e2)) => ("/", e1, e2),
+        Expr::Int(i) => return int(*i),
+        Expr::Error => return String::from("?"),
+    };
+    format!("{} {} {}", op, expr(e1), expr(e2))
+}
+
+fn int(i: i32) -> String {
+    match i {
+        // Its magnitude is out of range itself
+        i32::MIN => format!("- - 0 {} 1", i32::MAX),
+        i if i < 0 => format!("- 0 {}", -i),
+        i => i.to_string(),
+    }
+}
+
+/// Renders a program as a Graphviz DOT graph, with a node per statement and
+/// per expression and edges from each operator to its operands, left first.
+/// With `values`, each expression is annotated with what it evaluates to.
+///
+/// ```text
+/// digraph ast {
+///   ordering=out;
+///   n0 [label="Print"];
+///   n1 [label="+"];
+///   n0 -> n1;
+///   ...
+/// }
+/// ```
+pub fn dot(program: &Program, values: bool) -> String {
+    let mut out = String::from("digraph ast {\n  ordering=out;\n");
+    // Every node's value by id, for nodes numbered in the order they are drawn
+    let mut evaluated = Vec::new();
+    let mut next = 0;
+    for stmt in program {
+        let (label, e) = match &stmt.node {
+            Stmt::Expr(e) => ("Expr", e),
+            Stmt::Print(e) => ("Print", e),
+        };
+        let id = next;
+        next += 1;
+        if values {
+            evaluated.push(None);
+            // Failures are drawn on the nodes they reach
+            let _ = evaluate(e, &mut evaluated);
+        }
+        out.push_str(&format!(
+            "  n{} [label=\"{}\", shape=plaintext];\n",
+            id, label
+        ));
+        let child = dot_expr(e, &evaluated, &mut next, &mut out);
+        out.push_str(&format!("  n{} -> n{};\n", id, child));
+    }
+    out.push_str("}\n");
+    out
+}
+
+// Works out the value of `node` and everything under it in one pass, adding
+// them to `evaluated` in the order they are drawn
+fn evaluate(node: &Node, evaluated: &mut Vec<Option<Result<i32, String>>>) -> Result<i32, String> {
+    let id = evaluated.len();
+    evaluated.push(None);
+    let value = match &node.node {
+        Expr::Add((e1, e2)) | Expr::Sub((e1, e2)) | Expr::Mult((e1, e2)) | Expr::Div((e1, e2)) => {
+            let e1 = evaluate(e1, evaluated);
+            let e2 = evaluate(e2, evaluated);
+            e1.and_then(|e1| {
+                interpreter::operate(node, e1, e2?, Numeric::default()).map_err(|e| e.to_string())
+            })
+        }
+        _ => interpreter::interpreter(node.clone()).map_err(|e| e.to_string()),
+    };
+    evaluated[id] = Some(value.clone());
+    value
+}
+
+// Writes out `node` and everything under it, returning its id
+fn dot_expr(
+    node: &Node,
+    evaluated: &[Option<Result<i32, String>>],
+    next: &mut usize,
+    out: &mut String,
+) -> usize {
+    let (label, children) = match &node.node {
+        Expr::Add((e1, e2)) => (String::from("+"), vec![e1, e2]),
+        Expr::Sub((e1, e2)) => (String::from("-"), vec![e1, e2]),
+        Expr::Mult((e1, e2)) => (String::from("*"), vec![e1, e2]),
+        Expr::Div((e1, e2)) => (String::from("/"), vec![e1, e2]),
+        Expr::Int(i) => (i.to_string(), vec![]),
+        Expr::Error => (String::from("?"), vec![]),
+    };
+    let id = *next;
+    *next += 1;
+    // A literal's value would only repeat its label
+    let label = match (&node.node, evaluated.get(id)) {
+        (Expr::Int(_), _) => escape(&label),
+        (_, Some(Some(Ok(value)))) => format!("{}\\n= {}", escape(&label), value),
+        (_, Some(Some(Err(e)))) => format!("{}\\n{}", escape(&label), escape(e)),
+        _ => escape(&label),
+    };
+    out.push_str(&format!("  n{} [label=\"{}\"];\n", id, label));
+    for child in children {
+        let child = dot_expr(child, evaluated, next, out);
+        out.push_str(&format!("  n{} -> n{};\n", id, child));
+    }
+    id
+}
+
+// Text as it can go between the quotes of a DOT string
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{lexer, parser};
+    use super::*;
+
+    #[test]
+    fn nested() {
+        let program = parser::program(lexer::lexer("print + 5 * 4 3\n7", 0).unwrap()).unwrap();
+        assert_eq!(
+            tree(&program),
+            "Print @1:1\n\
+             └─ Add @1:7\n   \
+             ├─ Int 5 @1:9\n   \
+             └─ Mult @1:11\n      \
+             ├─ Int 4 @1:13\n      \
+             └─ Int 3 @1:15\n\
+             Expr @2:1\n\
+             └─ Int 7 @2:1\n"
+        );
+    }
+
+    #[test]
+    fn source() {
+        let program =
+            parser::program(lexer::lexer("print   +5 *\t4 3 ; - 1 2", 0).unwrap()).unwrap();
+        let rendered: Vec<String> = program.iter().map(statement).collect();
+        assert_eq!(rendered, vec!["print + 5 * 4 3", "- 1 2"]);
+    }
+
+    #[test]
+    fn graph() {
+        let program = parser::program(lexer::lexer("print + 5 * 4 3", 0).unwrap()).unwrap();
+        assert_eq!(
+            dot(&program, false),
+            "digraph ast {\n  \
+             ordering=out;\n  \
+             n0 [label=\"Print\", shape=plaintext];\n  \
+             n1 [label=\"+\"];\n  \
+             n2 [label=\"5\"];\n  \
+             n1 -> n2;\n  \
+             n3 [label=\"*\"];\n  \
+             n4 [label=\"4\"];\n  \
+             n3 -> n4;\n  \
+             n5 [label=\"3\"];\n  \
+             n3 -> n5;\n  \
+             n1 -> n3;\n  \
+             n0 -> n1;\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn graph_values() {
+        let program = parser::program(lexer::lexer("+ 5 * 4 3; / 1 0", 0).unwrap()).unwrap();
+        let graph = dot(&program, true);
+        assert!(graph.contains("n1 [label=\"+\\n= 17\"];"));
+        assert!(graph.contains("n3 [label=\"*\\n= 12\"];"));
+        assert!(graph.contains("n2 [label=\"5\"];"));
+        assert!(graph.contains("n7 [label=\"/\\ndivision by zero\"];"));
+    }
+
+    #[test]
+    fn graph_escapes() {
+        assert_eq!(escape(r#"a "b" \c"#), r#"a \"b\" \\c"#);
+        // A failure shows on every node it reaches, and nowhere else
+        let program = parser::program(lexer::lexer("+ / 1 0 - 5 2", 0).unwrap()).unwrap();
+        let graph = dot(&program, true);
+        assert!(graph.contains("n1 [label=\"+\\ndivision by zero\"];"));
+        assert!(graph.contains("n5 [label=\"-\\n= 3\"];"));
+    }
+}
@@ -0,0 +1,541 @@
+use super::super::error::decode::{Error, Result};
+use super::super::json::Value;
+use super::super::span::{Span, Spanned};
+use super::{Expr, Node, Program, Stmt, Tok, Token};
+use std::mem;
+
+// Each operator's name in JSON and its symbol in S-expressions
+const OPERATORS: [(&str, &str); 4] = [("add", "+"), ("sub", "-"), ("mult", "*"), ("div", "/")];
+
+// Every token but `TokInt` by its name in JSON
+const TOKENS: [(&str, Tok); 7] = [
+    ("add", Tok::TokAdd),
+    ("sub", Tok::TokSub),
+    ("mult", Tok::TokMult),
+    ("div", Tok::TokDiv),
+    ("print", Tok::TokPrint),
+    ("semi", Tok::TokSemi),
+    ("newline", Tok::TokNewline),
+];
+
+// An operator node as its index in `OPERATORS` and its operands
+fn split(expr: &Expr) -> Option<(usize, &Node, &Node)> {
+    match expr {
+        Expr::Add((e1, e2)) => Some((0, e1, e2)),
+        Expr::Sub((e1, e2)) => Some((1, e1, e2)),
+        Expr::Mult((e1, e2)) => Some((2, e1, e2)),
+        Expr::Div((e1, e2)) => Some((3, e1, e2)),
+        Expr::Int(_) | Expr::Error => None,
+    }
+}
+
+fn join(op: usize, e1: Node, e2: Node) -> Expr {
+    let operands = (Box::new(e1), Box::new(e2));
+    match op {
+        0 => Expr::Add(operands),
+        1 => Expr::Sub(operands),
+        2 => Expr::Mult(operands),
+        _ => Expr::Div(operands),
+    }
+}
+
+/// An expression as JSON. Every node has a `kind`, one of `add`, `sub`,
+/// `mult`, `div`, `int` or `error`, and a `span` of byte offsets plus the line
+/// and column they start at. Operators have their operands as `left` and
+/// `right`, and literals their `value`:
+///
+/// ```text
+/// {"kind":"add",
+///  "left":{"kind":"int","value":5,"span":{"start":2,"end":3,"line":1,"col":3}},
+///  "right":{"kind":"int","value":4,"span":{"start":4,"end":5,"line":1,"col":5}},
+///  "span":{"start":0,"end":5,"line":1,"col":1}}
+/// ```
+pub fn expr_to_json(node: &Node) -> Value {
+    let mut fields = match (&node.node, split(&node.node)) {
+        (_, Some((op, e1, e2))) => vec![
+            ("kind", Value::from(OPERATORS[op].0)),
+            ("left", expr_to_json(e1)),
+            ("right", expr_to_json(e2)),
+        ],
+        (Expr::Int(i), _) => vec![("kind", Value::from("int")), ("value", Value::from(*i))],
+        _ => vec![("kind", Value::from("error"))],
+    };
+    fields.push(("span", Value::from(node.span)));
+    Value::object(fields)
+}
+
+/// A program as a JSON array of statements, each with a `kind` of `print` or
+/// `expr`, the expression as `expr` (see [`expr_to_json`]) and a `span`.
+pub fn program_to_json(program: &Program) -> Value {
+    Value::Array(
+        program
+            .iter()
+            .map(|stmt| {
+                let (kind, e) = match &stmt.node {
+                    Stmt::Expr(e) => ("expr", e),
+                    Stmt::Print(e) => ("print", e),
+                };
+                Value::object(vec![
+                    ("kind", Value::from(kind)),
+                    ("expr", expr_to_json(e)),
+                    ("span", Value::from(stmt.span)),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// A token stream as a JSON array of tokens, each with a `kind` of `add`,
+/// `sub`, `mult`, `div`, `int`, `print`, `semi` or `newline` and a `span`.
+/// Integers have their `value`.
+pub fn tokens_to_json(tokens: &[Token]) -> Value {
+    Value::Array(
+        tokens
+            .iter()
+            .map(|tok| {
+                let mut fields = match tok.node {
+                    Tok::TokInt(i) => vec![("kind", Value::from("int")), ("value", Value::from(i))],
+                    tok => {
+                        let (name, _) = TOKENS
+                            .iter()
+                            .find(|(_, named)| *named == tok)
+                            .expect("every other token is named");
+                        vec![("kind", Value::from(*name))]
+                    }
+                };
+                fields.push(("span", Value::from(tok.span)));
+                Value::object(fields)
+            })
+            .collect(),
+    )
+}
+
+/// Loads an expression written by [`expr_to_json`]. A `span` may be left out,
+/// for tools that have none to give, and is then `Span::default()`.
+pub fn expr_from_json(text: &str) -> Result<Node> {
+    json_expr(&parse_json(text)?, "$")
+}
+
+/// Loads a program written by [`program_to_json`], allowing missing spans.
+pub fn program_from_json(text: &str) -> Result<Program> {
+    let value = parse_json(text)?;
+    let stmts = value
+        .as_array()
+        .ok_or_else(|| Error::invalid_at("an array of statements", "$"))?;
+    stmts
+        .iter()
+        .enumerate()
+        .map(|(i, stmt)| {
+            let path = format!("$[{}]", i);
+            let e = json_expr(field(stmt, &path, "expr")?, &format!("{}.expr", path))?;
+            let node = match string(stmt, &path, "kind")? {
+                "expr" => Stmt::Expr(e),
+                "print" => Stmt::Print(e),
+                _ => {
+                    return Err(Error::invalid_at(
+                        "`expr` or `print`",
+                        &format!("{}.kind", path),
+                    ))
+                }
+            };
+            Ok(Spanned::new(node, json_span(stmt, &path)?))
+        })
+        .collect()
+}
+
+/// Loads a token stream written by [`tokens_to_json`], allowing missing spans.
+pub fn tokens_from_json(text: &str) -> Result<Vec<Token>> {
+    let value = parse_json(text)?;
+    let tokens = value
+        .as_array()
+        .ok_or_else(|| Error::invalid_at("an array of tokens", "$"))?;
+    tokens
+        .iter()
+        .enumerate()
+        .map(|(i, tok)| {
+            let path = format!("$[{}]", i);
+            let node = match string(tok, &path, "kind")? {
+                "int" => Tok::TokInt(json_int(tok, &path)?),
+                kind => match TOKENS.iter().find(|(name, _)| *name == kind) {
+                    Some((_, tok)) => *tok,
+                    None => {
+                        return Err(Error::invalid_at("a token kind", &format!("{}.kind", path)))
+                    }
+                },
+            };
+            Ok(Spanned::new(node, json_span(tok, &path)?))
+        })
+        .collect()
+}
+
+fn parse_json(text: &str) -> Result<Value> {
+    Value::parse(text).map_err(Error::syntax)
+}
+
+fn json_expr(value: &Value, path: &str) -> Result<Node> {
+    let node = match string(value, path, "kind")? {
+        "int" => Expr::Int(json_int(value, path)?),
+        "error" => Expr::Error,
+        kind => match OPERATORS.iter().position(|(name, _)| *name == kind) {
+            Some(op) => {
+                let e1 = json_expr(field(value, path, "left")?, &format!("{}.left", path))?;
+                let e2 = json_expr(field(value, path, "right")?, &format!("{}.right", path))?;
+                join(op, e1, e2)
+            }
+            None => {
+                return Err(Error::invalid_at(
+                    "an expression kind",
+                    &format!("{}.kind", path),
+                ))
+            }
+        },
+    };
+    Ok(Spanned::new(node, json_span(value, path)?))
+}
+
+fn field<'a>(value: &'a Value, path: &str, key: &str) -> Result<&'a Value> {
+    value
+        .get(key)
+        .ok_or_else(|| Error::invalid_at(&format!("a `{}` field", key), path))
+}
+
+fn string<'a>(value: &'a Value, path: &str, key: &str) -> Result<&'a str> {
+    field(value, path, key)?
+        .as_str()
+        .ok_or_else(|| Error::invalid_at("a string", &format!("{}.{}", path, key)))
+}
+
+// A whole number within `min..=max`
+fn number(value: &Value, path: &str, key: &str, min: f64, max: f64) -> Result<f64> {
+    field(value, path, key)?
+        .as_f64()
+        .filter(|n| n.fract() == 0.0 && (min..=max).contains(n))
+        .ok_or_else(|| Error::invalid_at("an integer", &format!("{}.{}", path, key)))
+}
+
+fn json_int(value: &Value, path: &str) -> Result<i32> {
+    Ok(number(value, path, "value", i32::MIN.into(), i32::MAX.into())? as i32)
+}
+
+fn json_span(value: &Value, path: &str) -> Result<Span> {
+    let Some(span) = value.get("span") else {
+        return Ok(Span::default());
+    };
+    let path = format!("{}.span", path);
+    let offset = |key| Ok(number(span, &path, key, 0.0, u32::MAX.into())? as usize);
+    let (start, end) = (offset("start")?, offset("end")?);
+    if start > end {
+        return Err(Error::invalid_at(
+            "a span that does not end before it starts",
+            &path,
+        ));
+    }
+    Ok(Span {
+        start,
+        end,
+        line: offset("line")?,
+        col: offset("col")?,
+    })
+}
+
+/// An expression as an S-expression in prefix notation, e.g. `(+ 5 (* 4 3))`.
+/// An `Error` node is written `?`.
+pub fn expr_to_sexpr(node: &Node) -> String {
+    match (&node.node, split(&node.node)) {
+        (_, Some((op, e1, e2))) => format!(
+            "({} {} {})",
+            OPERATORS[op].1,
+            expr_to_sexpr(e1),
+            expr_to_sexpr(e2)
+        ),
+        (Expr::Int(i), _) => i.to_string(),
+        _ => String::from("?"),
+    }
+}
+
+/// A program as one S-expression per line: `(print <expr>)` or
+/// `(expr <expr>)`.
+pub fn program_to_sexpr(program: &Program) -> String {
+    let mut out = String::new();
+    for stmt in program {
+        let (kind, e) = match &stmt.node {
+            Stmt::Expr(e) => ("expr", e),
+            Stmt::Print(e) => ("print", e),
+        };
+        out.push_str(&format!("({} {})\n", kind, expr_to_sexpr(e)));
+    }
+    out
+}
+
+/// A token stream as a list of the tokens as written, e.g.
+/// `(print + 12 3 ; newline)`.
+pub fn tokens_to_sexpr(tokens: &[Token]) -> String {
+    let tokens: Vec<String> = tokens.iter().map(|tok| tok.node.to_string()).collect();
+    format!("({})", tokens.join(" "))
+}
+
+/// Loads an expression written by [`expr_to_sexpr`]. S-expressions carry no
+/// spans, so each node is given its span in `text` instead.
+pub fn expr_from_sexpr(text: &str) -> Result<Node> {
+    sexpr_expr(&single(text, "one expression")?)
+}
+
+/// Loads a program written by [`program_to_sexpr`], with spans into `text`.
+pub fn program_from_sexpr(text: &str) -> Result<Program> {
+    read(text)?
+        .iter()
+        .map(|form| {
+            let node = match &form.node {
+                Sexpr::List(items) => match &items[..] {
+                    [kind, e] if kind.node == Sexpr::atom("expr") => {
+                        Some(Stmt::Expr(sexpr_expr(e)?))
+                    }
+                    [kind, e] if kind.node == Sexpr::atom("print") => {
+                        Some(Stmt::Print(sexpr_expr(e)?))
+                    }
+                    _ => None,
+                },
+                Sexpr::Atom(_) => None,
+            };
+            let node = node.ok_or_else(|| Error::invalid("a statement").with_span(form.span))?;
+            Ok(Spanned::new(node, form.span))
+        })
+        .collect()
+}
+
+/// Loads a token stream written by [`tokens_to_sexpr`], with spans into `text`.
+pub fn tokens_from_sexpr(text: &str) -> Result<Vec<Token>> {
+    let form = single(text, "one list of tokens")?;
+    let Sexpr::List(items) = &form.node else {
+        return Err(Error::invalid("a list of tokens").with_span(form.span));
+    };
+    items
+        .iter()
+        .map(|item| {
+            let tok = match &item.node {
+                Sexpr::Atom(atom) => match atom.as_str() {
+                    "+" => Some(Tok::TokAdd),
+                    "-" => Some(Tok::TokSub),
+                    "*" => Some(Tok::TokMult),
+                    "/" => Some(Tok::TokDiv),
+                    "print" => Some(Tok::TokPrint),
+                    ";" => Some(Tok::TokSemi),
+                    "newline" => Some(Tok::TokNewline),
+                    atom => atom.parse().ok().map(Tok::TokInt),
+                },
+                Sexpr::List(_) => None,
+            };
+            tok.map(|tok| Spanned::new(tok, item.span))
+                .ok_or_else(|| Error::invalid("a token").with_span(item.span))
+        })
+        .collect()
+}
+
+#[derive(PartialEq, Debug)]
+enum Sexpr {
+    Atom(String),
+    List(Vec<Spanned<Sexpr>>),
+}
+
+impl Sexpr {
+    fn atom(atom: &str) -> Self {
+        Sexpr::Atom(atom.to_string())
+    }
+}
+
+// Every top-level form in `text`
+fn read(text: &str) -> Result<Vec<Spanned<Sexpr>>> {
+    // Lists not yet closed, with where each began and the forms before it
+    let mut open: Vec<(usize, Vec<Spanned<Sexpr>>)> = Vec::new();
+    let mut forms = Vec::new();
+    let mut pos = 0;
+    while let Some(c) = text[pos..].chars().next() {
+        match c {
+            '(' => {
+                open.push((pos, mem::take(&mut forms)));
+                pos += 1;
+            }
+            ')' => {
+                let Some((start, outer)) = open.pop() else {
+                    return Err(Error::syntax(String::from("unexpected `)`"))
+                        .with_span(Span::new(text, pos, pos + 1)));
+                };
+                pos += 1;
+                let list = Sexpr::List(mem::replace(&mut forms, outer));
+                forms.push(Spanned::new(list, Span::new(text, start, pos)));
+            }
+            c if c.is_whitespace() => pos += c.len_utf8(),
+            _ => {
+                let end = text[pos..]
+                    .find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                    .map_or(text.len(), |i| pos + i);
+                let atom = Sexpr::atom(&text[pos..end]);
+                forms.push(Spanned::new(atom, Span::new(text, pos, end)));
+                pos = end;
+            }
+        }
+    }
+    match open.last() {
+        Some((start, _)) => Err(
+            Error::syntax(String::from("unclosed `(`")).with_span(Span::new(
+                text,
+                *start,
+                start + 1,
+            )),
+        ),
+        None => Ok(forms),
+    }
+}
+
+// The only form in `text`, which should be `expected`
+fn single(text: &str, expected: &str) -> Result<Spanned<Sexpr>> {
+    let mut forms = read(text)?;
+    match forms.len() {
+        1 => Ok(forms.remove(0)),
+        0 => Err(Error::invalid(expected).with_span(Span::new(text, text.len(), text.len()))),
+        _ => Err(Error::invalid(expected).with_span(forms[1].span)),
+    }
+}
+
+fn sexpr_expr(form: &Spanned<Sexpr>) -> Result<Node> {
+    let invalid = || Error::invalid("an expression").with_span(form.span);
+    let node = match &form.node {
+        Sexpr::Atom(atom) if atom == "?" => Expr::Error,
+        Sexpr::Atom(atom) => Expr::Int(atom.parse().map_err(|_| invalid())?),
+        Sexpr::List(items) => match &items[..] {
+            [op, e1, e2] => {
+                let op = OPERATORS
+                    .iter()
+                    .position(|(_, symbol)| op.node == Sexpr::atom(symbol))
+                    .ok_or_else(invalid)?;
+                join(op, sexpr_expr(e1)?, sexpr_expr(e2)?)
+            }
+            _ => return Err(invalid()),
+        },
+    };
+    Ok(Spanned::new(node, form.span))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{interpreter, lexer, parser, pretty};
+    use super::*;
+
+    fn parse(input: &str) -> Program {
+        parser::program(lexer::lexer(input, 0).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn json_schema() {
+        let program = parse("print + 5 4");
+        assert_eq!(
+            program_to_json(&program).to_string(),
+            r#"[{"kind":"print","expr":{"kind":"add","#.to_string()
+                + r#""left":{"kind":"int","value":5,"span":{"start":8,"end":9,"line":1,"col":9}},"#
+                + r#""right":{"kind":"int","value":4,"span":{"start":10,"end":11,"line":1,"col":11}},"#
+                + r#""span":{"start":6,"end":11,"line":1,"col":7}},"#
+                + r#""span":{"start":0,"end":11,"line":1,"col":1}}]"#
+        );
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let program = parse("print + 5 * 4 3\n/ 1 - 5 3; 7");
+        let text = program_to_json(&program).to_string();
+        assert_eq!(program_from_json(&text).unwrap(), program);
+
+        let tokens = lexer::lexer("print + 12 3 ; 4\n", 0).unwrap();
+        let text = tokens_to_json(&tokens).to_string();
+        assert_eq!(tokens_from_json(&text).unwrap(), tokens);
+    }
+
+    #[test]
+    fn json_without_spans() {
+        let node = expr_from_json(
+            r#"{"kind":"mult","left":{"kind":"int","value":6},"right":{"kind":"int","value":7}}"#,
+        )
+        .unwrap();
+        assert_eq!(node.span, Span::default());
+        assert_eq!(interpreter::interpreter(node).unwrap(), 42);
+    }
+
+    #[test]
+    fn json_errors() {
+        let err =
+            program_from_json(r#"[{"kind":"print","expr":{"kind":"add","left":1}}]"#).unwrap_err();
+        assert_eq!(err.code(), "D002");
+        assert_eq!(
+            err.to_string(),
+            "expected a `kind` field at `$[0].expr.left`"
+        );
+
+        let err = expr_from_json(r#"{"kind":"mod"}"#).unwrap_err();
+        assert_eq!(err.to_string(), "expected an expression kind at `$.kind`");
+
+        let err = expr_from_json(r#"{"kind":"int","value":2.5}"#).unwrap_err();
+        assert_eq!(err.to_string(), "expected an integer at `$.value`");
+
+        let err = expr_from_json(
+            r#"{"kind":"int","value":2,"span":{"start":5,"end":4,"line":1,"col":6}}"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), "D002");
+        assert_eq!(
+            err.to_string(),
+            "expected a span that does not end before it starts at `$.span`"
+        );
+
+        let err = tokens_from_json("[{").unwrap_err();
+        assert_eq!(err.code(), "D001");
+        assert_eq!(
+            err.to_string(),
+            "malformed input: at byte 2: expected a key, found end of input"
+        );
+    }
+
+    #[test]
+    fn sexpr_round_trip() {
+        let program = parse("print + 5 * 4 3\n/ 1 - 5 3; 7");
+        let text = program_to_sexpr(&program);
+        assert_eq!(
+            text,
+            "(print (+ 5 (* 4 3)))\n(expr (/ 1 (- 5 3)))\n(expr 7)\n"
+        );
+        let loaded = program_from_sexpr(&text).unwrap();
+        let statements: Vec<String> = loaded.iter().map(pretty::statement).collect();
+        assert_eq!(statements, vec!["print + 5 * 4 3", "/ 1 - 5 3", "7"]);
+        assert_eq!(
+            &text[loaded[0].span.start..loaded[0].span.end],
+            "(print (+ 5 (* 4 3)))"
+        );
+
+        // Negative literals can be written, though the calculator has none
+        let node = expr_from_sexpr("(- 2 -3)").unwrap();
+        assert_eq!(interpreter::interpreter(node).unwrap(), 5);
+
+        let tokens = lexer::lexer("print + 12 3 ; 4\n", 0).unwrap();
+        let text = tokens_to_sexpr(&tokens);
+        assert_eq!(text, "(print + 12 3 ; 4 newline)");
+        let toks: Vec<Tok> = tokens.iter().map(|tok| tok.node).collect();
+        assert_eq!(tokens_from_sexpr(&text).unwrap(), toks);
+    }
+
+    #[test]
+    fn sexpr_errors() {
+        let text = "(+ 1 (* 2 3)";
+        let err = expr_from_sexpr(text).unwrap_err();
+        assert_eq!(err.code(), "D001");
+        assert_eq!(err.span(), Some(Span::new(text, 0, 1)));
+
+        let text = "(+ 1 (% 2 3))";
+        let err = expr_from_sexpr(text).unwrap_err();
+        assert_eq!(err.to_string(), "expected an expression");
+        assert_eq!(err.span(), Some(Span::new(text, 5, 12)));
+
+        let err = expr_from_sexpr("1 2").unwrap_err();
+        assert_eq!(err.to_string(), "expected one expression");
+
+        let err = program_from_sexpr("(print 1)\n(show 2)").unwrap_err();
+        assert_eq!(err.span().map(|span| span.line), Some(2));
+    }
+}
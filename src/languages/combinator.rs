@@ -0,0 +1,514 @@
+use super::error::parser::{Error, Result};
+use super::span::{Span, Spanned};
+use std::cell::OnceCell;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+/// What a language's tokens must be to be parsed with combinators.
+pub trait Terminal: Clone + PartialEq + fmt::Debug + 'static {}
+
+impl<T: Clone + PartialEq + fmt::Debug + 'static> Terminal for T {}
+
+/// The tokens being parsed, and where to point when they run out.
+pub struct Input<'a, T> {
+    toks: &'a [Spanned<T>],
+    eof: Span,
+}
+
+// What a parser made and the position after it, or where it failed and why.
+// Failing past the position it started at means it had taken input, and so
+// committed to its alternative.
+type Outcome<T, O> = std::result::Result<(O, usize), (usize, Error<T, Vec<T>>)>;
+
+type Run<T, O> = dyn Fn(&Input<T>, usize) -> Outcome<T, O>;
+
+/// Parses a prefix of a token stream into an `O`. Parsers are built up from
+/// [`token`] and [`select`] with the combinators in this module, and are
+/// cheap to clone.
+pub struct Parser<T, O> {
+    run: Rc<Run<T, O>>,
+}
+
+impl<T, O> Clone for Parser<T, O> {
+    fn clone(&self) -> Self {
+        Parser {
+            run: Rc::clone(&self.run),
+        }
+    }
+}
+
+impl<T: Terminal, O: 'static> Parser<T, O> {
+    fn new(run: impl Fn(&Input<T>, usize) -> Outcome<T, O> + 'static) -> Self {
+        Parser { run: Rc::new(run) }
+    }
+
+    /// Parses the whole of `toks`; input left over is a `TokensNotEmpty` error.
+    pub fn parse(&self, toks: &[Spanned<T>]) -> Result<O, T, Vec<T>> {
+        let eof = toks.last().map_or(Span::default(), |tok| tok.span.after());
+        let (value, pos) = (self.run)(&Input { toks, eof }, 0).map_err(|(_, err)| err)?;
+        match (toks.get(pos), toks.last()) {
+            (Some(first), Some(last)) => Err(Error::tokens_not_empty(unspanned(&toks[pos..]))
+                .with_span(first.span.to(last.span))),
+            _ => Ok(value),
+        }
+    }
+
+    /// Transforms what this parser makes.
+    pub fn map<P: 'static>(self, f: impl Fn(O) -> P + 'static) -> Parser<T, P> {
+        Parser::new(move |input: &Input<T>, pos| {
+            let (value, pos) = (self.run)(input, pos)?;
+            Ok((f(value), pos))
+        })
+    }
+
+    /// Runs this parser and then `next`, making both their results.
+    pub fn then<P: 'static>(self, next: Parser<T, P>) -> Parser<T, (O, P)> {
+        Parser::new(move |input: &Input<T>, pos| {
+            let (first, pos) = (self.run)(input, pos)?;
+            let (second, pos) = (next.run)(input, pos)?;
+            Ok(((first, second), pos))
+        })
+    }
+
+    /// This parser, or `other` if this one fails without taking any input.
+    pub fn or(self, other: Parser<T, O>) -> Parser<T, O> {
+        choice(vec![self, other])
+    }
+
+    /// Names the grammar rule this parser recognises. When it fails without
+    /// taking any input, the failure is reported as a `ProductionRuleFailure`
+    /// of the rule, unless the input had run out.
+    pub fn label(self, name: &str) -> Parser<T, O> {
+        let name = name.to_string();
+        Parser::new(move |input: &Input<T>, pos| {
+            (self.run)(input, pos).map_err(|(at, err)| match input.toks.get(pos) {
+                Some(tok) if at == pos => {
+                    let rest = unspanned(&input.toks[pos..]);
+                    let err = Error::production_rule_failure(name.clone(), rest);
+                    (at, err.with_span(tok.span))
+                }
+                _ => (at, err),
+            })
+        })
+    }
+}
+
+/// Matches exactly the token `expected`.
+pub fn token<T: Terminal>(expected: T) -> Parser<T, Spanned<T>> {
+    Parser::new(move |input: &Input<T>, pos| match input.toks.get(pos) {
+        Some(tok) if tok.node == expected => Ok((tok.clone(), pos + 1)),
+        Some(tok) => {
+            let rest = unspanned(&input.toks[pos..]);
+            let err = Error::mismatched_token(expected.clone(), rest, tok.node.clone());
+            Err((pos, err.with_span(tok.span)))
+        }
+        None => Err((pos, Error::tokens_empty().with_span(input.eof))),
+    })
+}
+
+/// Matches any one token `f` makes something of, such as a literal.
+pub fn select<T: Terminal, O: 'static>(f: fn(&T) -> Option<O>) -> Parser<T, Spanned<O>> {
+    Parser::new(move |input: &Input<T>, pos| match input.toks.get(pos) {
+        Some(tok) => match f(&tok.node) {
+            Some(value) => Ok((Spanned::new(value, tok.span), pos + 1)),
+            None => Err((
+                pos,
+                Error::invalid_input(tok.node.clone()).with_span(tok.span),
+            )),
+        },
+        None => Err((pos, Error::tokens_empty().with_span(input.eof))),
+    })
+}
+
+/// The first of `alternatives` to succeed. An alternative that fails after
+/// taking input has committed, so the others are not tried. When every one
+/// fails at the start the first one's error is reported, so a choice is
+/// usually [labelled](Parser::label).
+pub fn choice<T: Terminal, O: 'static>(alternatives: Vec<Parser<T, O>>) -> Parser<T, O> {
+    Parser::new(move |input: &Input<T>, pos| {
+        let mut first_err = None;
+        for alternative in &alternatives {
+            match (alternative.run)(input, pos) {
+                Ok(parsed) => return Ok(parsed),
+                Err((at, err)) if at > pos => return Err((at, err)),
+                Err(failed) => {
+                    first_err.get_or_insert(failed);
+                }
+            }
+        }
+        Err(first_err.unwrap_or_else(|| (pos, Error::tokens_empty().with_span(input.eof))))
+    })
+}
+
+/// `parser` as many times as it matches, possibly none.
+pub fn many<T: Terminal, O: 'static>(parser: Parser<T, O>) -> Parser<T, Vec<O>> {
+    Parser::new(move |input: &Input<T>, mut pos| {
+        let mut values = Vec::new();
+        loop {
+            match (parser.run)(input, pos) {
+                // Matching nothing would match nothing forever
+                Ok((value, next)) if next == pos => {
+                    values.push(value);
+                    return Ok((values, pos));
+                }
+                Ok((value, next)) => {
+                    values.push(value);
+                    pos = next;
+                }
+                Err((at, err)) if at > pos => return Err((at, err)),
+                Err(_) => return Ok((values, pos)),
+            }
+        }
+    })
+}
+
+/// `parser` if it matches, or nothing.
+pub fn optional<T: Terminal, O: 'static>(parser: Parser<T, O>) -> Parser<T, Option<O>> {
+    Parser::new(
+        move |input: &Input<T>, pos| match (parser.run)(input, pos) {
+            Ok((value, pos)) => Ok((Some(value), pos)),
+            Err((at, err)) if at > pos => Err((at, err)),
+            Err(_) => Ok((None, pos)),
+        },
+    )
+}
+
+/// The parser `rule` makes, built only when it is needed, so that rules can
+/// refer to themselves. Each `lazy` builds its parser once, the first time it
+/// runs; to build a recursive rule just once in all, use [`recursive`].
+pub fn lazy<T: Terminal, O: 'static>(rule: fn() -> Parser<T, O>) -> Parser<T, O> {
+    let built = OnceCell::new();
+    Parser::new(move |input: &Input<T>, pos| (built.get_or_init(rule).run)(input, pos))
+}
+
+/// A parser that refers to itself: `rule` is handed the parser being defined,
+/// to use wherever it recurses, and is called once.
+pub fn recursive<T: Terminal, O: 'static>(
+    rule: impl FnOnce(Parser<T, O>) -> Parser<T, O>,
+) -> Parser<T, O> {
+    let defined: Rc<OnceCell<Parser<T, O>>> = Rc::new(OnceCell::new());
+    // The parser holds itself only weakly, so dropping it frees it
+    let weak = Rc::downgrade(&defined);
+    let this = Parser::new(move |input: &Input<T>, pos| {
+        let defined = Weak::upgrade(&weak).expect("a parser only runs while it is held");
+        let parser = defined
+            .get()
+            .expect("a parser only runs once it is defined");
+        (parser.run)(input, pos)
+    });
+    defined.get_or_init(|| rule(this));
+    Parser::new(move |input: &Input<T>, pos| {
+        (defined.get().expect("defined above").run)(input, pos)
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+/// An infix operator for [`precedence`].
+pub struct Operator<T, O> {
+    pub token: T,
+    /// Higher binds tighter
+    pub precedence: u8,
+    pub assoc: Assoc,
+    /// Makes the result of applying the operator to its operands
+    pub combine: fn(O, Spanned<T>, O) -> O,
+}
+
+/// Operands separated by infix operators, grouped by precedence climbing,
+/// e.g. `1 + 2 * 3` as `1 + (2 * 3)`.
+pub fn precedence<T: Terminal, O: 'static>(
+    operand: Parser<T, O>,
+    operators: Vec<Operator<T, O>>,
+) -> Parser<T, O> {
+    Parser::new(move |input: &Input<T>, pos| climb(&operand, &operators, 0, input, pos))
+}
+
+// Operands joined by operators binding at least as tightly as `min`, which is
+// wider than a precedence so that nothing binds tighter than 255
+fn climb<T: Terminal, O>(
+    operand: &Parser<T, O>,
+    operators: &[Operator<T, O>],
+    min: u16,
+    input: &Input<T>,
+    pos: usize,
+) -> Outcome<T, O> {
+    let (mut lhs, mut pos) = (operand.run)(input, pos)?;
+    while let Some(tok) = input.toks.get(pos) {
+        let Some(op) = operators
+            .iter()
+            .find(|op| op.token == tok.node && u16::from(op.precedence) >= min)
+        else {
+            break;
+        };
+        let next_min = match op.assoc {
+            Assoc::Left => u16::from(op.precedence) + 1,
+            Assoc::Right => u16::from(op.precedence),
+        };
+        let (rhs, next) = climb(operand, operators, next_min, input, pos + 1)?;
+        lhs = (op.combine)(lhs, tok.clone(), rhs);
+        pos = next;
+    }
+    Ok((lhs, pos))
+}
+
+fn unspanned<T: Clone>(toks: &[Spanned<T>]) -> Vec<T> {
+    toks.iter().map(|tok| tok.node.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::calculator::{lexer, Expr, Node, Tok};
+    use super::*;
+
+    // The calculator's prefix expressions, declaratively
+    fn e() -> Parser<Tok, Node> {
+        let binary = |tok, expr: fn((Box<Node>, Box<Node>)) -> Expr| {
+            token(tok)
+                .then(lazy(e))
+                .then(lazy(e))
+                .map(move |((op, e1), e2)| {
+                    let span = op.span.to(e2.span);
+                    Spanned::new(expr((Box::new(e1), Box::new(e2))), span)
+                })
+        };
+        choice(vec![
+            binary(Tok::TokAdd, Expr::Add),
+            binary(Tok::TokSub, Expr::Sub),
+            binary(Tok::TokMult, Expr::Mult),
+            binary(Tok::TokDiv, Expr::Div),
+            select(|tok| match tok {
+                Tok::TokInt(i) => Some(Expr::Int(*i)),
+                _ => None,
+            }),
+        ])
+        .label("E rule")
+    }
+
+    fn calculator(input: &str) -> Result<Node, Tok, Vec<Tok>> {
+        e().parse(&lexer::lexer(input, 0).unwrap())
+    }
+
+    #[test]
+    fn prefix_grammar() {
+        let input = "+ 5 * 4 3";
+        let toks = lexer::lexer(input, 0).unwrap();
+        assert_eq!(
+            e().parse(&toks).unwrap(),
+            super::super::calculator::parser::parser(toks).unwrap()
+        );
+    }
+
+    #[test]
+    fn labelled_errors() {
+        let err = calculator("+ 1 print 2").unwrap_err();
+        assert_eq!(err.code(), "P005");
+        assert_eq!(err.to_string(), "could not parse input as E rule");
+        assert_eq!(err.span(), Some(Span::new("+ 1 print 2", 4, 9)));
+
+        let err = calculator("+ 5").unwrap_err();
+        assert!(err.is_tokens_empty());
+        assert_eq!(err.span(), Some(Span::new("+ 5", 3, 3)));
+
+        let err = calculator("5 6 7").unwrap_err();
+        assert_eq!(err.code(), "P004");
+        assert_eq!(err.span(), Some(Span::new("5 6 7", 2, 5)));
+    }
+
+    // An infix language: numbers, `+` and `*`, right-associative `^`, and
+    // parenthesised groups
+    #[derive(Clone, PartialEq, Debug)]
+    enum Sym {
+        Num(i64),
+        Plus,
+        Star,
+        Caret,
+        Open,
+        Close,
+    }
+
+    impl fmt::Display for Sym {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    // Each symbol as if written one character apart
+    fn spanned(syms: Vec<Sym>) -> Vec<Spanned<Sym>> {
+        syms.into_iter()
+            .enumerate()
+            .map(|(i, sym)| {
+                let span = Span {
+                    start: i,
+                    end: i + 1,
+                    line: 1,
+                    col: i + 1,
+                };
+                Spanned::new(sym, span)
+            })
+            .collect()
+    }
+
+    fn infix() -> Parser<Sym, i64> {
+        let number = select(|sym| match sym {
+            Sym::Num(n) => Some(*n),
+            _ => None,
+        })
+        .map(|n| n.node);
+        let group = token(Sym::Open)
+            .then(lazy(infix))
+            .then(token(Sym::Close))
+            .map(|((_, n), _)| n);
+        let operator = |token, precedence, assoc, combine| Operator {
+            token,
+            precedence,
+            assoc,
+            combine,
+        };
+        precedence(
+            number.or(group).label("operand"),
+            vec![
+                operator(Sym::Plus, 1, Assoc::Left, |a, _, b| a + b),
+                operator(Sym::Star, 2, Assoc::Left, |a, _, b| a * b),
+                operator(Sym::Caret, 3, Assoc::Right, |a, _, b| a.pow(b as u32)),
+            ],
+        )
+    }
+
+    #[test]
+    fn precedence_climbing() {
+        use Sym::*;
+        // 1 + 2 * 3 ^ 2 ^ 2 = 1 + 2 * 81
+        let syms = vec![
+            Num(1),
+            Plus,
+            Num(2),
+            Star,
+            Num(3),
+            Caret,
+            Num(2),
+            Caret,
+            Num(2),
+        ];
+        assert_eq!(infix().parse(&spanned(syms)).unwrap(), 163);
+        // (1 + 2) * 3
+        let syms = vec![Open, Num(1), Plus, Num(2), Close, Star, Num(3)];
+        assert_eq!(infix().parse(&spanned(syms)).unwrap(), 9);
+    }
+
+    #[test]
+    fn highest_precedence() {
+        use Sym::*;
+        let parser = precedence(
+            select(|sym| match sym {
+                Num(n) => Some(*n),
+                _ => None,
+            })
+            .map(|n| n.node),
+            vec![Operator {
+                token: Plus,
+                precedence: u8::MAX,
+                assoc: Assoc::Left,
+                combine: |a, _, b| a - b,
+            }],
+        );
+        // Read as (5 - 2) - 1
+        let syms = vec![Num(5), Plus, Num(2), Plus, Num(1)];
+        assert_eq!(parser.parse(&spanned(syms)).unwrap(), 2);
+    }
+
+    thread_local! {
+        static BUILDS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    fn number() -> Parser<Sym, i64> {
+        select(|sym| match sym {
+            Sym::Num(n) => Some(*n),
+            _ => None,
+        })
+        .map(|n| n.node)
+    }
+
+    fn nested() -> Parser<Sym, i64> {
+        BUILDS.with(|builds| builds.set(builds.get() + 1));
+        token(Sym::Open)
+            .then(lazy(nested))
+            .then(token(Sym::Close))
+            .map(|((_, n), _)| n)
+            .or(number())
+    }
+
+    // Twenty pairs of parentheses around a number
+    fn parenthesised() -> Vec<Spanned<Sym>> {
+        let mut syms = vec![Sym::Open; 20];
+        syms.push(Sym::Num(7));
+        syms.extend(vec![Sym::Close; 20]);
+        spanned(syms)
+    }
+
+    #[test]
+    fn lazy_builds_once() {
+        let parser = nested();
+        assert_eq!(parser.parse(&parenthesised()).unwrap(), 7);
+        // Once for the call above and once for each level the input reached
+        assert_eq!(BUILDS.with(|builds| builds.get()), 21);
+        // Every `lazy` has been built, so parsing again builds nothing
+        assert_eq!(parser.parse(&parenthesised()).unwrap(), 7);
+        assert_eq!(BUILDS.with(|builds| builds.get()), 21);
+    }
+
+    #[test]
+    fn recursive_builds_once() {
+        let mut builds = 0;
+        // Held by the parser, to see that dropping the parser frees it
+        let held = Rc::new(());
+        let in_parser = Rc::clone(&held);
+        let parser = recursive(|this| {
+            builds += 1;
+            token(Sym::Open)
+                .then(this)
+                .then(token(Sym::Close))
+                .map(move |((_, n), _)| {
+                    let _ = &in_parser;
+                    n
+                })
+                .or(number())
+        });
+        assert_eq!(parser.parse(&parenthesised()).unwrap(), 7);
+        assert_eq!(builds, 1);
+        assert!(parser
+            .parse(&spanned(vec![Sym::Open, Sym::Num(1)]))
+            .is_err());
+        drop(parser);
+        assert_eq!(Rc::strong_count(&held), 1);
+    }
+
+    #[test]
+    fn committed_errors() {
+        use Sym::*;
+        let err = infix()
+            .parse(&spanned(vec![Open, Num(1), Plus, Num(2), Num(3)]))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "expected `Close`, found `Num(3)`");
+
+        let err = infix()
+            .parse(&spanned(vec![Num(1), Plus, Star]))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "could not parse input as operand");
+        assert_eq!(err.span().map(|span| span.start), Some(2));
+    }
+
+    #[test]
+    fn repetition() {
+        use Sym::*;
+        let pluses = many(token(Plus)).then(optional(token(Star)));
+        let (found, star) = pluses.parse(&spanned(vec![Plus, Plus, Star])).unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(star.is_some());
+        let (found, star) = pluses.parse(&[]).unwrap();
+        assert!(found.is_empty() && star.is_none());
+    }
+}
@@ -0,0 +1,177 @@
+use super::json::Value;
+use super::span::Span;
+use std::fmt;
+
+/// The stage of running a program that found a problem.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Phase {
+    Lexer,
+    Parser,
+    Interpreter,
+    /// Loading a serialized syntax tree or token stream
+    Decode,
+}
+
+impl Phase {
+    /// The phase's name in JSON diagnostics, e.g. `parser`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Phase::Lexer => "lexer",
+            Phase::Parser => "parser",
+            Phase::Interpreter => "interpreter",
+            Phase::Decode => "decode",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Phase::Lexer => write!(f, "Lexer"),
+            Phase::Parser => write!(f, "Parser"),
+            Phase::Interpreter => write!(f, "Interpreter"),
+            Phase::Decode => write!(f, "Decode"),
+        }
+    }
+}
+
+/// A problem found in a program, in the one form every language's errors
+/// are shown in. Each phase's `Error` converts into one, and a language can
+/// add notes and help that only make sense for it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Diagnostic {
+    pub phase: Phase,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Option<Span>,
+    /// The tokens or input the problem is about, for tools
+    pub related: Vec<String>,
+    /// Further facts about the problem
+    pub notes: Vec<String>,
+    /// A suggestion for fixing it
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(phase: Phase, code: &'static str, message: String, span: Option<Span>) -> Self {
+        Diagnostic {
+            phase,
+            code,
+            message,
+            span,
+            related: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn with_related(mut self, related: Vec<String>) -> Self {
+        self.related = related;
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn with_help(mut self, help: &str) -> Self {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    /// The diagnostic as one JSON object, for editors and CI.
+    pub fn to_json(&self) -> Value {
+        Value::object(vec![
+            ("phase", Value::from(self.phase.as_str())),
+            ("code", Value::from(self.code)),
+            ("severity", Value::from("error")),
+            ("message", Value::from(self.message.clone())),
+            ("span", Value::from(self.span)),
+            ("related", Value::from(self.related.clone())),
+            ("notes", Value::from(self.notes.clone())),
+            ("help", Value::from(self.help.clone())),
+        ])
+    }
+
+    /// Renders the diagnostic for people, against the `src` it was found in:
+    /// where it is, when `src` came from the file `origin`; the offending line
+    /// underlined; the headline after `prefix`; then any notes and help.
+    ///
+    /// ```text
+    /// --> work.calc:1:7
+    /// + 1 2 3
+    ///       ^
+    /// error: Parser error [P004]: unexpected input after the end of the expression
+    /// help: a statement is a single expression; ...
+    /// ```
+    pub fn render(&self, src: &str, origin: Option<&str>, prefix: &str) -> String {
+        let mut out = String::new();
+        if let Some(span) = self.span {
+            if let Some(path) = origin {
+                out.push_str(&format!("--> {}:{}\n", path, span));
+            }
+            out.push_str(&format!("{}\n", span.underline(src)));
+        }
+        out.push_str(&format!("{}{}\n", prefix, self));
+        for note in &self.notes {
+            out.push_str(&format!("note: {}\n", note));
+        }
+        if let Some(help) = &self.help {
+            out.push_str(&format!("help: {}\n", help));
+        }
+        out
+    }
+}
+
+// The headline, e.g. `Parser error [P003]: unexpected end of input`
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} error [{}]: {}", self.phase, self.code, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overflow(src: &str) -> Diagnostic {
+        Diagnostic::new(
+            Phase::Interpreter,
+            "I005",
+            String::from("arithmetic overflow"),
+            Some(Span::new(src, 2, 9)),
+        )
+        .with_note("numbers are 32-bit signed integers")
+        .with_help("divide first")
+    }
+
+    #[test]
+    fn render() {
+        let src = "* 65536 65536";
+        assert_eq!(
+            overflow(src).render(src, Some("big.calc"), "error: "),
+            "--> big.calc:1:3\n\
+             * 65536 65536\n  \
+             ^~~~~~~\n\
+             error: Interpreter error [I005]: arithmetic overflow\n\
+             note: numbers are 32-bit signed integers\n\
+             help: divide first\n"
+        );
+        let unplaced = Diagnostic::new(Phase::Lexer, "L001", String::from("oops"), None);
+        assert_eq!(unplaced.render("", None, ""), "Lexer error [L001]: oops\n");
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            overflow("* 65536 65536").to_json().to_string(),
+            concat!(
+                r#"{"phase":"interpreter","code":"I005","severity":"error","#,
+                r#""message":"arithmetic overflow","#,
+                r#""span":{"start":2,"end":9,"line":1,"col":3},"related":[],"#,
+                r#""notes":["numbers are 32-bit signed integers"],"help":"divide first"}"#
+            )
+        );
+    }
+}
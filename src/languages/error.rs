@@ -1,13 +1,22 @@
+//! The errors of each phase of running a program. Every error has a code,
+//! e.g. `P003`, shown with it and kept in its JSON; codes are part of the
+//! public interface, so they are never renumbered, only appended to.
+
 pub mod parser {
     use core::result;
     use std::error::Error as StdError;
     use std::fmt;
 
+    use super::super::diagnostic::{Diagnostic, Phase};
+    use super::super::json::Value;
+    use super::super::span::Span;
+
     pub type Result<E, T, U> = result::Result<E, Error<T, U>>;
 
     // T reps a languages tokens; U reps a Vec of a language's tokens
     pub struct Error<T, U> {
         kind: ErrorKind<T, U>,
+        span: Option<Span>,
     }
 
     #[derive(Debug, Clone)]
@@ -30,33 +39,36 @@ pub mod parser {
         },
     }
 
-    impl<T: fmt::Debug, U: fmt::Debug> StdError for ErrorKind<T, U> {}
+    impl<T: fmt::Debug + fmt::Display, U: fmt::Debug> StdError for ErrorKind<T, U> {}
 
-    impl<T: fmt::Debug, U: fmt::Debug> fmt::Display for ErrorKind<T, U> {
+    impl<T: fmt::Display, U> fmt::Display for ErrorKind<T, U> {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             match self {
-                ErrorKind::InvalidInput { input } => write!(f, "invalid input: {:?}", input),
+                ErrorKind::InvalidInput { input } => write!(f, "invalid input `{}`", input),
                 ErrorKind::MismatchedToken {
                     expected_tok,
-                    tok_list,
                     actual_tok,
-                } => write!(
-                    f,
-                    "expected {:?} from {:?} but got {:?}",
-                    expected_tok, tok_list, actual_tok
-                ),
-                ErrorKind::TokensEmpty => write!(f, "token list is empty"),
-                ErrorKind::TokensNotEmpty { tok_list } => {
-                    write!(f, "tokens list is not empty: {:?}", tok_list)
+                    ..
+                } => write!(f, "expected `{}`, found `{}`", expected_tok, actual_tok),
+                ErrorKind::TokensEmpty => write!(f, "unexpected end of input"),
+                ErrorKind::TokensNotEmpty { .. } => {
+                    write!(f, "unexpected input after the end of the expression")
                 }
-                ErrorKind::ProductionRuleFailure {
-                    prod_rule,
-                    tok_list,
-                } => write!(
-                    f,
-                    "production rule failure: [{}]; tokens list: {:?}",
-                    prod_rule, tok_list
-                ),
+                ErrorKind::ProductionRuleFailure { prod_rule, .. } => {
+                    write!(f, "could not parse input as {}", prod_rule)
+                }
+            }
+        }
+    }
+
+    impl<T, U> ErrorKind<T, U> {
+        fn code(&self) -> &'static str {
+            match self {
+                ErrorKind::InvalidInput { .. } => "P001",
+                ErrorKind::MismatchedToken { .. } => "P002",
+                ErrorKind::TokensEmpty => "P003",
+                ErrorKind::TokensNotEmpty { .. } => "P004",
+                ErrorKind::ProductionRuleFailure { .. } => "P005",
             }
         }
     }
@@ -67,10 +79,72 @@ pub mod parser {
         }
     }
 
+    impl<T: fmt::Display, U> fmt::Display for Error<T, U> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", self.kind)
+        }
+    }
+
+    impl<T: fmt::Debug + fmt::Display, U: fmt::Debug> StdError for Error<T, U> {}
+
+    impl<T, U> Error<T, U> {
+        pub fn code(&self) -> &'static str {
+            self.kind.code()
+        }
+
+        /// The error as a JSON diagnostic; `related` lists the tokens it refers to.
+        pub fn to_json(&self) -> Value
+        where
+            T: fmt::Display,
+            for<'a> &'a U: IntoIterator<Item = &'a T>,
+        {
+            Diagnostic::from(self).to_json()
+        }
+
+        /// Whether parsing failed because the input ended mid-expression.
+        pub fn is_tokens_empty(&self) -> bool {
+            matches!(self.kind, ErrorKind::TokensEmpty)
+        }
+
+        /// The remaining tokens at the point of failure, where the error records them.
+        pub fn tok_list(&self) -> Option<&U> {
+            match &self.kind {
+                ErrorKind::MismatchedToken { tok_list, .. }
+                | ErrorKind::TokensNotEmpty { tok_list }
+                | ErrorKind::ProductionRuleFailure { tok_list, .. } => Some(tok_list),
+                ErrorKind::InvalidInput { .. } | ErrorKind::TokensEmpty => None,
+            }
+        }
+    }
+
+    impl<T: fmt::Display, U> From<&Error<T, U>> for Diagnostic
+    where
+        for<'a> &'a U: IntoIterator<Item = &'a T>,
+    {
+        fn from(err: &Error<T, U>) -> Self {
+            let related = match &err.kind {
+                ErrorKind::InvalidInput { input } => vec![input.to_string()],
+                ErrorKind::MismatchedToken {
+                    expected_tok,
+                    actual_tok,
+                    ..
+                } => vec![expected_tok.to_string(), actual_tok.to_string()],
+                ErrorKind::TokensEmpty => vec![],
+                ErrorKind::TokensNotEmpty { tok_list }
+                | ErrorKind::ProductionRuleFailure { tok_list, .. } => {
+                    tok_list.into_iter().map(|tok| tok.to_string()).collect()
+                }
+            };
+            Diagnostic::new(Phase::Parser, err.code(), err.kind.to_string(), err.span)
+                .with_related(related)
+        }
+    }
+
     impl<T: fmt::Debug + Clone, U: fmt::Debug + Clone> Error<T, U> {
         pub fn invalid_input(input: T) -> Self {
             Self {
                 kind: ErrorKind::InvalidInput { input },
+                span: None,
             }
         }
 
@@ -81,18 +155,21 @@ pub mod parser {
                     tok_list,
                     actual_tok,
                 },
+                span: None,
             }
         }
 
         pub fn tokens_empty() -> Self {
             Self {
                 kind: ErrorKind::TokensEmpty,
+                span: None,
             }
         }
 
         pub fn tokens_not_empty(tok_list: U) -> Self {
             Self {
                 kind: ErrorKind::TokensNotEmpty { tok_list },
+                span: None,
             }
         }
 
@@ -102,8 +179,18 @@ pub mod parser {
                     prod_rule,
                     tok_list,
                 },
+                span: None,
             }
         }
+
+        pub fn with_span(mut self, span: Span) -> Self {
+            self.span = Some(span);
+            self
+        }
+
+        pub fn span(&self) -> Option<Span> {
+            self.span
+        }
     }
 }
 
@@ -112,11 +199,16 @@ pub mod lexer {
     use std::error::Error as StdError;
     use std::fmt;
 
+    use super::super::diagnostic::{Diagnostic, Phase};
+    use super::super::json::Value;
+    use super::super::span::Span;
+
     pub type Result<E, T> = result::Result<E, Error<T>>;
 
     // T reps a languages tokens; U reps a Vec of a language's tokens
     pub struct Error<T> {
         kind: ErrorKind<T>,
+        span: Option<Span>,
     }
 
     #[derive(Debug, Clone)]
@@ -124,12 +216,20 @@ pub mod lexer {
         InvalidInput { input: T },
     }
 
-    impl<T: fmt::Debug> StdError for ErrorKind<T> {}
+    impl<T: fmt::Debug + fmt::Display> StdError for ErrorKind<T> {}
 
-    impl<T: fmt::Debug> fmt::Display for ErrorKind<T> {
+    impl<T: fmt::Display> fmt::Display for ErrorKind<T> {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             match self {
-                ErrorKind::InvalidInput { input } => write!(f, "invalid input: {:?}", input),
+                ErrorKind::InvalidInput { input } => write!(f, "unrecognised input `{}`", input),
+            }
+        }
+    }
+
+    impl<T> ErrorKind<T> {
+        fn code(&self) -> &'static str {
+            match self {
+                ErrorKind::InvalidInput { .. } => "L001",
             }
         }
     }
@@ -140,12 +240,53 @@ pub mod lexer {
         }
     }
 
+    impl<T: fmt::Display> fmt::Display for Error<T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", self.kind)
+        }
+    }
+
+    impl<T: fmt::Debug + fmt::Display> StdError for Error<T> {}
+
+    impl<T> Error<T> {
+        pub fn code(&self) -> &'static str {
+            self.kind.code()
+        }
+
+        pub fn to_json(&self) -> Value
+        where
+            T: fmt::Display,
+        {
+            Diagnostic::from(self).to_json()
+        }
+    }
+
+    impl<T: fmt::Display> From<&Error<T>> for Diagnostic {
+        fn from(err: &Error<T>) -> Self {
+            let related = match &err.kind {
+                ErrorKind::InvalidInput { input } => vec![input.to_string()],
+            };
+            Diagnostic::new(Phase::Lexer, err.code(), err.kind.to_string(), err.span)
+                .with_related(related)
+        }
+    }
+
     impl<T: fmt::Debug + Clone> Error<T> {
         pub fn invalid_input(input: T) -> Self {
             Self {
                 kind: ErrorKind::InvalidInput { input },
+                span: None,
             }
         }
+
+        pub fn with_span(mut self, span: Span) -> Self {
+            self.span = Some(span);
+            self
+        }
+
+        pub fn span(&self) -> Option<Span> {
+            self.span
+        }
     }
 }
 
@@ -154,17 +295,25 @@ pub mod interpreter {
     use std::error::Error as StdError;
     use std::fmt;
 
+    use super::super::diagnostic::{Diagnostic, Phase};
+    use super::super::json::Value;
+    use super::super::span::Span;
+
     pub type Result<E> = result::Result<E, Error>;
 
     // T reps a languages tokens; U reps a Vec of a language's tokens
     pub struct Error {
         kind: ErrorKind,
+        span: Option<Span>,
     }
 
     #[derive(Debug, Clone)]
     enum ErrorKind {
         DivByZero,
         InvalidType(String),
+        ErrorNode,
+        Io(String),
+        Overflow,
     }
 
     impl StdError for ErrorKind {}
@@ -172,8 +321,25 @@ pub mod interpreter {
     impl fmt::Display for ErrorKind {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             match self {
-                ErrorKind::DivByZero => write!(f, "divide by zero"),
-                ErrorKind::InvalidType(type_name) => write!(f, "incorrect type: {:?}", type_name),
+                ErrorKind::DivByZero => write!(f, "division by zero"),
+                ErrorKind::InvalidType(type_name) => write!(f, "incorrect type `{}`", type_name),
+                ErrorKind::ErrorNode => {
+                    write!(f, "cannot evaluate an expression that failed to parse")
+                }
+                ErrorKind::Io(message) => write!(f, "could not write output: {}", message),
+                ErrorKind::Overflow => write!(f, "arithmetic overflow"),
+            }
+        }
+    }
+
+    impl ErrorKind {
+        fn code(&self) -> &'static str {
+            match self {
+                ErrorKind::DivByZero => "I001",
+                ErrorKind::InvalidType(_) => "I002",
+                ErrorKind::ErrorNode => "I003",
+                ErrorKind::Io(_) => "I004",
+                ErrorKind::Overflow => "I005",
             }
         }
     }
@@ -184,16 +350,228 @@ pub mod interpreter {
         }
     }
 
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", self.kind)
+        }
+    }
+
+    impl StdError for Error {}
+
+    impl From<&Error> for Diagnostic {
+        fn from(err: &Error) -> Self {
+            Diagnostic::new(
+                Phase::Interpreter,
+                err.code(),
+                err.kind.to_string(),
+                err.span,
+            )
+        }
+    }
+
     impl Error {
+        pub fn code(&self) -> &'static str {
+            self.kind.code()
+        }
+
+        pub fn to_json(&self) -> Value {
+            Diagnostic::from(self).to_json()
+        }
+
         pub fn div_by_zero() -> Self {
             Self {
                 kind: ErrorKind::DivByZero,
+                span: None,
+            }
+        }
+        pub fn error_node() -> Self {
+            Self {
+                kind: ErrorKind::ErrorNode,
+                span: None,
             }
         }
+
+        pub fn overflow() -> Self {
+            Self {
+                kind: ErrorKind::Overflow,
+                span: None,
+            }
+        }
+
+        pub fn io(err: &std::io::Error) -> Self {
+            Self {
+                kind: ErrorKind::Io(err.to_string()),
+                span: None,
+            }
+        }
+
         pub fn invalid_type(type_name: &str) -> Self {
             Self {
                 kind: ErrorKind::InvalidType(type_name.to_string()),
+                span: None,
+            }
+        }
+
+        pub fn with_span(mut self, span: Span) -> Self {
+            self.span = Some(span);
+            self
+        }
+
+        pub fn span(&self) -> Option<Span> {
+            self.span
+        }
+    }
+}
+
+pub mod decode {
+    use core::result;
+    use std::error::Error as StdError;
+    use std::fmt;
+
+    use super::super::diagnostic::{Diagnostic, Phase};
+    use super::super::json::Value;
+    use super::super::span::Span;
+
+    pub type Result<E> = result::Result<E, Error>;
+
+    /// A failure to load a serialized syntax tree or token stream.
+    pub struct Error {
+        kind: ErrorKind,
+        span: Option<Span>,
+    }
+
+    #[derive(Debug, Clone)]
+    enum ErrorKind {
+        Syntax(String),
+        Invalid {
+            expected: String,
+            path: Option<String>,
+        },
+    }
+
+    impl StdError for ErrorKind {}
+
+    impl fmt::Display for ErrorKind {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                ErrorKind::Syntax(message) => write!(f, "malformed input: {}", message),
+                ErrorKind::Invalid {
+                    expected,
+                    path: Some(path),
+                } => write!(f, "expected {} at `{}`", expected, path),
+                ErrorKind::Invalid {
+                    expected,
+                    path: None,
+                } => write!(f, "expected {}", expected),
+            }
+        }
+    }
+
+    impl ErrorKind {
+        fn code(&self) -> &'static str {
+            match self {
+                ErrorKind::Syntax(_) => "D001",
+                ErrorKind::Invalid { .. } => "D002",
+            }
+        }
+    }
+
+    impl fmt::Debug for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{:?}", self.kind)
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", self.kind)
+        }
+    }
+
+    impl StdError for Error {}
+
+    impl From<&Error> for Diagnostic {
+        fn from(err: &Error) -> Self {
+            let related = match &err.kind {
+                ErrorKind::Invalid {
+                    path: Some(path), ..
+                } => vec![path.clone()],
+                _ => vec![],
+            };
+            Diagnostic::new(Phase::Decode, err.code(), err.kind.to_string(), err.span)
+                .with_related(related)
+        }
+    }
+
+    impl Error {
+        pub fn code(&self) -> &'static str {
+            self.kind.code()
+        }
+
+        /// The error as a JSON diagnostic; `related` holds the path into the
+        /// document, where there is one.
+        pub fn to_json(&self) -> Value {
+            Diagnostic::from(self).to_json()
+        }
+
+        /// The text is not JSON, or not S-expressions.
+        pub fn syntax(message: String) -> Self {
+            Self {
+                kind: ErrorKind::Syntax(message),
+                span: None,
             }
         }
+
+        /// The text parsed, but something in it is not what was `expected`.
+        pub fn invalid(expected: &str) -> Self {
+            Self {
+                kind: ErrorKind::Invalid {
+                    expected: expected.to_string(),
+                    path: None,
+                },
+                span: None,
+            }
+        }
+
+        /// Like [`Error::invalid`], for the value at `path` in a JSON document,
+        /// e.g. `$[0].expr.left`.
+        pub fn invalid_at(expected: &str, path: &str) -> Self {
+            Self {
+                kind: ErrorKind::Invalid {
+                    expected: expected.to_string(),
+                    path: Some(path.to_string()),
+                },
+                span: None,
+            }
+        }
+
+        pub fn with_span(mut self, span: Span) -> Self {
+            self.span = Some(span);
+            self
+        }
+
+        pub fn span(&self) -> Option<Span> {
+            self.span
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as StdError;
+
+    #[test]
+    fn sources() {
+        // A phase error is where a chain of causes ends, so reporters that
+        // walk the chain print its message once
+        let err = super::parser::Error::<String, Vec<String>>::tokens_empty();
+        assert!(err.source().is_none());
+        assert!(super::lexer::Error::invalid_input(String::from("%"))
+            .source()
+            .is_none());
+        assert!(super::interpreter::Error::div_by_zero().source().is_none());
+        assert!(super::decode::Error::invalid_at("a number", "$.value")
+            .source()
+            .is_none());
     }
 }
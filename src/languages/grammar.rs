@@ -0,0 +1,549 @@
+use super::error::parser::{Error, Result};
+use super::span::{Span, Spanned};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// Stands for the empty string, in grammars and in FIRST sets.
+pub const EPSILON: &str = "ε";
+/// The lookahead at the end of the input, in FOLLOW sets.
+pub const END: &str = "$";
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Symbol {
+    Terminal(String),
+    Nonterminal(usize),
+}
+
+#[derive(Clone, Debug)]
+struct Production {
+    lhs: usize,
+    rhs: Vec<Symbol>,
+}
+
+/// A context-free grammar read from BNF, with its FIRST and FOLLOW sets.
+///
+/// Each line gives one or more alternatives for a nonterminal, separated by
+/// `|`, and the first nonterminal is the start symbol:
+///
+/// ```text
+/// # A calculator program
+/// P  -> S Ps | ε
+/// Ps -> ; P | newline P | ε
+/// S  -> print E | E
+/// E  -> + E E | - E E | * E E | / E E | int
+/// ```
+///
+/// Symbols are separated by whitespace. Any symbol that is not defined by a
+/// line of its own is a terminal, as is anything in single quotes, such as
+/// `'|'`. `ε`, or an empty alternative, matches nothing.
+#[derive(Clone, Debug)]
+pub struct Grammar {
+    nonterminals: Vec<String>,
+    productions: Vec<Production>,
+    first: Vec<BTreeSet<String>>,
+    follow: Vec<BTreeSet<String>>,
+}
+
+impl Grammar {
+    /// Reads a grammar. Errors give the line they were found on.
+    pub fn parse(text: &str) -> std::result::Result<Grammar, String> {
+        let mut lines = Vec::new();
+        let mut nonterminals: Vec<String> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((lhs, rhs)) = line.split_once("->") else {
+                return Err(format!("{}: expected `<name> -> <alternatives>`", i + 1));
+            };
+            let lhs = lhs.trim();
+            if lhs.is_empty() || lhs.contains(char::is_whitespace) || lhs.starts_with('\'') {
+                return Err(format!("{}: `{}` is not a nonterminal name", i + 1, lhs));
+            }
+            if !nonterminals.iter().any(|name| name == lhs) {
+                nonterminals.push(lhs.to_string());
+            }
+            lines.push((lhs, rhs));
+        }
+        if nonterminals.is_empty() {
+            return Err(String::from("the grammar has no rules"));
+        }
+
+        let mut productions = Vec::new();
+        for (lhs, rhs) in lines {
+            let lhs = nonterminals.iter().position(|name| name == lhs).unwrap();
+            let mut alternative = Vec::new();
+            for word in rhs.split_whitespace().chain(["|"]) {
+                let symbol = match word {
+                    "|" => {
+                        let rhs = std::mem::take(&mut alternative);
+                        productions.push(Production { lhs, rhs });
+                        continue;
+                    }
+                    EPSILON => continue,
+                    word if word.len() > 2 && word.starts_with('\'') && word.ends_with('\'') => {
+                        Symbol::Terminal(word[1..word.len() - 1].to_string())
+                    }
+                    word => match nonterminals.iter().position(|name| name == word) {
+                        Some(nonterminal) => Symbol::Nonterminal(nonterminal),
+                        None => Symbol::Terminal(word.to_string()),
+                    },
+                };
+                alternative.push(symbol);
+            }
+        }
+
+        let mut grammar = Grammar {
+            first: vec![BTreeSet::new(); nonterminals.len()],
+            follow: vec![BTreeSet::new(); nonterminals.len()],
+            nonterminals,
+            productions,
+        };
+        grammar.compute_first();
+        grammar.compute_follow();
+        Ok(grammar)
+    }
+
+    /// The terminals that can begin `nonterminal`, including [`EPSILON`] if it
+    /// can match nothing. `None` if there is no such nonterminal.
+    pub fn first(&self, nonterminal: &str) -> Option<&BTreeSet<String>> {
+        self.index(nonterminal).map(|i| &self.first[i])
+    }
+
+    /// The terminals that can come after `nonterminal`, including [`END`] if it
+    /// can end the input.
+    pub fn follow(&self, nonterminal: &str) -> Option<&BTreeSet<String>> {
+        self.index(nonterminal).map(|i| &self.follow[i])
+    }
+
+    fn index(&self, nonterminal: &str) -> Option<usize> {
+        self.nonterminals
+            .iter()
+            .position(|name| name == nonterminal)
+    }
+
+    // The FIRST set of a sequence of symbols
+    fn first_of(&self, symbols: &[Symbol]) -> BTreeSet<String> {
+        let mut first = BTreeSet::new();
+        for symbol in symbols {
+            match symbol {
+                Symbol::Terminal(terminal) => {
+                    first.insert(terminal.clone());
+                    return first;
+                }
+                Symbol::Nonterminal(n) => {
+                    first.extend(self.first[*n].iter().filter(|t| *t != EPSILON).cloned());
+                    if !self.first[*n].contains(EPSILON) {
+                        return first;
+                    }
+                }
+            }
+        }
+        first.insert(EPSILON.to_string());
+        first
+    }
+
+    fn compute_first(&mut self) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for p in 0..self.productions.len() {
+                let first = self.first_of(&self.productions[p].rhs);
+                let lhs = self.productions[p].lhs;
+                let before = self.first[lhs].len();
+                self.first[lhs].extend(first);
+                changed |= self.first[lhs].len() != before;
+            }
+        }
+    }
+
+    fn compute_follow(&mut self) {
+        self.follow[0].insert(END.to_string());
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for p in 0..self.productions.len() {
+                let Production { lhs, rhs } = self.productions[p].clone();
+                for (i, symbol) in rhs.iter().enumerate() {
+                    let Symbol::Nonterminal(n) = symbol else {
+                        continue;
+                    };
+                    let mut follow = self.first_of(&rhs[i + 1..]);
+                    if follow.remove(EPSILON) {
+                        follow.extend(self.follow[lhs].iter().cloned());
+                    }
+                    let before = self.follow[*n].len();
+                    self.follow[*n].extend(follow);
+                    changed |= self.follow[*n].len() != before;
+                }
+            }
+        }
+    }
+
+    /// Builds the LL(1) parse table, or reports every place where one
+    /// lookahead leaves a choice of productions.
+    pub fn table(&self) -> std::result::Result<Table<'_>, Vec<Conflict>> {
+        let mut entries: HashMap<(usize, String), usize> = HashMap::new();
+        let mut conflicts: Vec<Conflict> = Vec::new();
+        for (p, production) in self.productions.iter().enumerate() {
+            let mut lookaheads = self.first_of(&production.rhs);
+            if lookaheads.remove(EPSILON) {
+                lookaheads.extend(self.follow[production.lhs].iter().cloned());
+            }
+            for lookahead in lookaheads {
+                let key = (production.lhs, lookahead);
+                let Some(&existing) = entries.get(&key) else {
+                    entries.insert(key, p);
+                    continue;
+                };
+                let nonterminal = &self.nonterminals[production.lhs];
+                match conflicts
+                    .iter_mut()
+                    .find(|c| c.nonterminal == *nonterminal && c.terminal == key.1)
+                {
+                    Some(conflict) => conflict.productions.push(self.render(p)),
+                    None => conflicts.push(Conflict {
+                        nonterminal: nonterminal.clone(),
+                        terminal: key.1,
+                        productions: vec![self.render(existing), self.render(p)],
+                    }),
+                }
+            }
+        }
+        if conflicts.is_empty() {
+            Ok(Table {
+                grammar: self,
+                entries,
+            })
+        } else {
+            Err(conflicts)
+        }
+    }
+
+    // A production as it would be written in the grammar
+    fn render(&self, p: usize) -> String {
+        let Production { lhs, rhs } = &self.productions[p];
+        let rhs: Vec<&str> = rhs
+            .iter()
+            .map(|symbol| match symbol {
+                Symbol::Terminal(terminal) => terminal.as_str(),
+                Symbol::Nonterminal(n) => self.nonterminals[*n].as_str(),
+            })
+            .collect();
+        let rhs = if rhs.is_empty() {
+            EPSILON.to_string()
+        } else {
+            rhs.join(" ")
+        };
+        format!("{} -> {}", self.nonterminals[*lhs], rhs)
+    }
+}
+
+/// Two or more productions for `nonterminal` that could each be chosen on
+/// seeing `terminal`.
+#[derive(PartialEq, Debug)]
+pub struct Conflict {
+    pub nonterminal: String,
+    pub terminal: String,
+    pub productions: Vec<String>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let productions: Vec<String> = self
+            .productions
+            .iter()
+            .map(|production| format!("`{}`", production))
+            .collect();
+        write!(
+            f,
+            "`{}` on `{}` could be any of {}",
+            self.nonterminal,
+            self.terminal,
+            productions.join(", ")
+        )
+    }
+}
+
+/// A parse tree: a token, or a nonterminal with what it was expanded to.
+#[derive(PartialEq, Debug)]
+pub enum Tree<T> {
+    Leaf(Spanned<T>),
+    Node {
+        rule: String,
+        children: Vec<Tree<T>>,
+    },
+}
+
+impl<T> Tree<T> {
+    /// The span of every token under this tree; `None` if it matched nothing.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Tree::Leaf(tok) => Some(tok.span),
+            Tree::Node { children, .. } => children
+                .iter()
+                .filter_map(Tree::span)
+                .reduce(|first, last| first.to(last)),
+        }
+    }
+}
+
+/// An LL(1) parse table for a [`Grammar`].
+pub struct Table<'g> {
+    grammar: &'g Grammar,
+    /// The production to expand each nonterminal by, for each lookahead
+    entries: HashMap<(usize, String), usize>,
+}
+
+// A nonterminal being expanded: which production, how far through it, and the
+// trees made so far
+struct Frame<T> {
+    production: usize,
+    next: usize,
+    children: Vec<Tree<T>>,
+}
+
+impl Table<'_> {
+    /// Parses `toks` from the grammar's start symbol. `terminal` names the
+    /// terminal each token stands for in the grammar.
+    ///
+    /// A token no production of the nonterminal being expanded can start
+    /// with, or that does not match the production chosen, fails as a
+    /// `ProductionRuleFailure` naming that nonterminal.
+    pub fn parse<T: fmt::Debug + Clone>(
+        &self,
+        toks: &[Spanned<T>],
+        terminal: fn(&T) -> &'static str,
+    ) -> Result<Tree<T>, T, Vec<T>> {
+        let eof = toks.last().map_or(Span::default(), |tok| tok.span.after());
+        let lookahead = |pos: usize| toks.get(pos).map_or(END, |tok| terminal(&tok.node));
+        let fail = |nonterminal: usize, pos: usize| match toks.get(pos) {
+            Some(tok) => Error::production_rule_failure(
+                self.grammar.nonterminals[nonterminal].clone(),
+                toks[pos..].iter().map(|tok| tok.node.clone()).collect(),
+            )
+            .with_span(tok.span),
+            None => Error::tokens_empty().with_span(eof),
+        };
+        let expand = |nonterminal: usize, pos: usize| {
+            let key = (nonterminal, lookahead(pos).to_string());
+            match self.entries.get(&key) {
+                Some(&production) => Ok(Frame {
+                    production,
+                    next: 0,
+                    children: Vec::new(),
+                }),
+                None => Err(fail(nonterminal, pos)),
+            }
+        };
+
+        let mut pos = 0;
+        let mut stack = vec![expand(0, pos)?];
+        loop {
+            let frame = stack.last_mut().expect("the stack is never left empty");
+            let Production { lhs, rhs } = &self.grammar.productions[frame.production];
+            match rhs.get(frame.next) {
+                Some(Symbol::Terminal(expected)) => {
+                    if lookahead(pos) != expected {
+                        return Err(fail(*lhs, pos));
+                    }
+                    frame.children.push(Tree::Leaf(toks[pos].clone()));
+                    frame.next += 1;
+                    pos += 1;
+                }
+                Some(Symbol::Nonterminal(n)) => {
+                    frame.next += 1;
+                    let inner = expand(*n, pos)?;
+                    stack.push(inner);
+                }
+                None => {
+                    let rule = self.grammar.nonterminals[*lhs].clone();
+                    let done = stack.pop().expect("this frame is on the stack");
+                    let tree = Tree::Node {
+                        rule,
+                        children: done.children,
+                    };
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(tree),
+                        None if pos < toks.len() => {
+                            let span = toks[pos].span.to(toks[toks.len() - 1].span);
+                            let rest = toks[pos..].iter().map(|tok| tok.node.clone()).collect();
+                            return Err(Error::tokens_not_empty(rest).with_span(span));
+                        }
+                        None => return Ok(tree),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::calculator::parser::GRAMMAR as CALCULATOR;
+    use super::super::calculator::{lexer, parser, Tok};
+    use super::*;
+
+    fn terminal(tok: &Tok) -> &'static str {
+        match tok {
+            Tok::TokAdd => "+",
+            Tok::TokSub => "-",
+            Tok::TokMult => "*",
+            Tok::TokDiv => "/",
+            Tok::TokInt(_) => "int",
+            Tok::TokPrint => "print",
+            Tok::TokSemi => ";",
+            Tok::TokNewline => "newline",
+        }
+    }
+
+    fn set(terminals: &[&str]) -> BTreeSet<String> {
+        terminals.iter().map(|t| t.to_string()).collect()
+    }
+
+    // The rules of the nodes of a tree, outermost first
+    fn rules(tree: &Tree<Tok>) -> Vec<String> {
+        match tree {
+            Tree::Leaf(tok) => vec![tok.node.to_string()],
+            Tree::Node { rule, children } => {
+                let mut found = vec![rule.clone()];
+                found.extend(children.iter().flat_map(rules));
+                found
+            }
+        }
+    }
+
+    #[test]
+    fn first_and_follow() {
+        let grammar = Grammar::parse(CALCULATOR).unwrap();
+        let expressions = ["+", "-", "*", "/", "int"];
+        assert_eq!(grammar.first("E"), Some(&set(&expressions)));
+        assert_eq!(
+            grammar.first("P"),
+            Some(&set(&[
+                "+", "-", "*", "/", "int", "print", ";", "newline", "ε"
+            ]))
+        );
+        assert_eq!(grammar.first("Ps"), Some(&set(&[";", "newline", "ε"])));
+        assert_eq!(grammar.follow("P"), Some(&set(&["$"])));
+        assert_eq!(grammar.follow("S"), Some(&set(&[";", "newline", "$"])));
+        let mut follow_e = set(&expressions);
+        follow_e.extend(set(&[";", "newline", "$"]));
+        assert_eq!(grammar.follow("E"), Some(&follow_e));
+        assert_eq!(grammar.first("Q"), None);
+    }
+
+    #[test]
+    fn parse_tree() {
+        let grammar = Grammar::parse(CALCULATOR).unwrap();
+        let table = grammar.table().unwrap();
+        let input = "print + 1 2; 3";
+        let tree = table
+            .parse(&lexer::lexer(input, 0).unwrap(), terminal)
+            .unwrap();
+        assert_eq!(
+            rules(&tree),
+            vec![
+                "P", "S", "print", "E", "+", "E", "1", "E", "2", "Ps", ";", "P", "S", "E", "3",
+                "Ps"
+            ]
+        );
+        assert_eq!(tree.span(), Some(Span::new(input, 0, 14)));
+    }
+
+    #[test]
+    fn agrees_with_program() {
+        let grammar = Grammar::parse(CALCULATOR).unwrap();
+        let table = grammar.table().unwrap();
+        let mut inputs = vec![
+            String::from("1\n\n2"),
+            String::from("\n1"),
+            String::from("1;\n2"),
+            String::from("# c\n1"),
+        ];
+        // Every line typed in the etop transcripts, but for commands
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/transcripts");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let transcript = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+            inputs.extend(
+                transcript
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("etop # ").or(line.strip_prefix("...  ")))
+                    .filter(|line| !line.starts_with(':'))
+                    .map(String::from),
+            );
+        }
+        for input in inputs {
+            let Ok(toks) = lexer::lexer(&input, 0) else {
+                continue;
+            };
+            assert_eq!(
+                table.parse(&toks, terminal).is_ok(),
+                parser::program(toks).is_ok(),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn rule_failures() {
+        let grammar = Grammar::parse(CALCULATOR).unwrap();
+        let table = grammar.table().unwrap();
+        let parse = |input| table.parse(&lexer::lexer(input, 0).unwrap(), terminal);
+
+        let err = parse("+ 1 print 2").unwrap_err();
+        assert_eq!(err.code(), "P005");
+        assert_eq!(err.to_string(), "could not parse input as E");
+        assert_eq!(err.span(), Some(Span::new("+ 1 print 2", 4, 9)));
+
+        let err = parse("+ 1").unwrap_err();
+        assert!(err.is_tokens_empty());
+        assert_eq!(err.span(), Some(Span::new("+ 1", 3, 3)));
+
+        let err = parse("1 2").unwrap_err();
+        assert_eq!(err.to_string(), "could not parse input as Ps");
+    }
+
+    #[test]
+    fn conflicts() {
+        // The grammar as first written down, which needs more than one token of
+        // lookahead to pick a production for `P`
+        let grammar = Grammar::parse(
+            "P -> S ; P | S newline P | S | ε\n\
+             S -> print E | E\n\
+             E -> + E E | int",
+        )
+        .unwrap();
+        let conflicts = grammar.table().err().unwrap();
+        let on_print = conflicts
+            .iter()
+            .find(|c| c.nonterminal == "P" && c.terminal == "print")
+            .unwrap();
+        assert_eq!(
+            on_print.to_string(),
+            "`P` on `print` could be any of `P -> S ; P`, `P -> S newline P`, `P -> S`"
+        );
+        assert_eq!(conflicts.len(), 3);
+    }
+
+    #[test]
+    fn quoted_terminals() {
+        let grammar = Grammar::parse("A -> x '|' A | '->' | ε").unwrap();
+        assert_eq!(grammar.first("A"), Some(&set(&["x", "->", "ε"])));
+    }
+
+    #[test]
+    fn grammar_errors() {
+        assert_eq!(
+            Grammar::parse("# rules\nE + E E").unwrap_err(),
+            "2: expected `<name> -> <alternatives>`"
+        );
+        assert_eq!(
+            Grammar::parse("A B -> c").unwrap_err(),
+            "1: `A B` is not a nonterminal name"
+        );
+        assert!(Grammar::parse("\n# nothing\n").is_err());
+    }
+}
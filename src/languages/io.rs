@@ -0,0 +1,267 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+/// Where an interpreter's programs read input from and write output to. Only
+/// bytes need implementing; characters and numbers are read on top of them.
+pub trait InterpreterIo {
+    /// The next byte of input, or `None` once there is no more.
+    fn read_byte(&mut self) -> io::Result<Option<u8>>;
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// The next UTF-8 encoded character of input, or `None` once there is no
+    /// more.
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        let first = match self.read_byte()? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+        let len = match first.leading_ones() {
+            0 => 1,
+            n @ 2..=4 => n as usize,
+            _ => return Err(invalid("input is not UTF-8")),
+        };
+        let mut bytes = vec![first];
+        while bytes.len() < len {
+            bytes.push(
+                self.read_byte()?
+                    .ok_or_else(|| invalid("input ends inside a character"))?,
+            );
+        }
+        let text = std::str::from_utf8(&bytes).map_err(|_| invalid("input is not UTF-8"))?;
+        Ok(text.chars().next())
+    }
+
+    /// Skips whitespace, then reads a decimal integer with an optional sign;
+    /// `None` means input ended first. The character ending the number is
+    /// consumed with it, as number input in most esoteric languages does.
+    fn read_number(&mut self) -> io::Result<Option<i64>> {
+        let mut c = match self.read_char()? {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        while c.is_whitespace() {
+            c = match self.read_char()? {
+                Some(c) => c,
+                None => return Ok(None),
+            };
+        }
+        let mut text = String::new();
+        if c == '-' || c == '+' {
+            text.push(c);
+            c = self.read_char()?.unwrap_or(' ');
+        }
+        while c.is_ascii_digit() {
+            text.push(c);
+            c = self.read_char()?.unwrap_or(' ');
+        }
+        text.parse()
+            .map(Some)
+            .map_err(|_| invalid(&format!("expected a number, found `{}{}`", text, c)))
+    }
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        s.bytes().try_for_each(|byte| self.write_byte(byte))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The process's stdin and stdout, for running programs on their own.
+pub struct Stdio;
+
+impl InterpreterIo for Stdio {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        loop {
+            match io::stdin().lock().read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        io::stdout().write_all(&[byte])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        io::stdout().write_all(s.as_bytes())
+    }
+}
+
+/// Input given up front and output kept in memory, for running programs
+/// deterministically, e.g. in tests.
+#[derive(Default)]
+pub struct Buffer {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl Buffer {
+    pub fn new(input: &str) -> Self {
+        Buffer {
+            input: input.bytes().collect(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Everything written so far, with any invalid UTF-8 replaced.
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.output).into_owned()
+    }
+
+    pub fn output_bytes(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+impl InterpreterIo for Buffer {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.input.pop_front())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.output.push(byte);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Output written through to any writer, with no input. This is how a plain
+/// `Write`, such as a file or a `Vec<u8>`, is handed to an interpreter.
+pub struct Output<W>(pub W);
+
+impl<W: Write> InterpreterIo for Output<W> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(None)
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.0.write_all(&[byte])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.0.write_all(s.as_bytes())
+    }
+}
+
+/// Input from a script of lines, each handed over with its newline as the
+/// program asks for more, and output written through to `out`. This is how
+/// programs run inside etop: they never read the terminal from under its
+/// prompt, and with no script their input is simply empty.
+pub struct Scripted<'a> {
+    script: VecDeque<u8>,
+    out: &'a mut dyn Write,
+}
+
+impl<'a> Scripted<'a> {
+    pub fn new(out: &'a mut dyn Write) -> Self {
+        Scripted {
+            script: VecDeque::new(),
+            out,
+        }
+    }
+
+    /// Adds a line to the end of the script.
+    pub fn line(mut self, text: &str) -> Self {
+        self.script.extend(text.bytes());
+        self.script.push_back(b'\n');
+        self
+    }
+}
+
+impl InterpreterIo for Scripted<'_> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.script.pop_front())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.out.write_all(&[byte])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.out.write_all(s.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chars_and_numbers() {
+        let mut io = Buffer::new("é1  -42\n+7x");
+        assert_eq!(io.read_char().unwrap(), Some('é'));
+        assert_eq!(io.read_number().unwrap(), Some(1));
+        assert_eq!(io.read_number().unwrap(), Some(-42));
+        assert_eq!(io.read_number().unwrap(), Some(7));
+        // The `x` ended the number
+        assert_eq!(io.read_byte().unwrap(), None);
+        assert_eq!(io.read_number().unwrap(), None);
+
+        let err = Buffer::new(" a").read_number().unwrap_err();
+        assert_eq!(err.to_string(), "expected a number, found `a`");
+        assert_eq!(Buffer::new("é").read_byte().unwrap(), Some(0xc3));
+        let mut io = Buffer::default();
+        io.input.extend([0xc3, 0x28]);
+        assert!(io.read_char().is_err());
+    }
+
+    #[test]
+    fn buffer_output() {
+        let mut io = Buffer::new("");
+        io.write_str("hi ").unwrap();
+        io.write_byte(b'!').unwrap();
+        io.flush().unwrap();
+        assert_eq!(io.output(), "hi !");
+        assert_eq!(io.output_bytes(), b"hi !");
+    }
+
+    #[test]
+    fn output() {
+        let mut io = Output(Vec::new());
+        assert_eq!(io.read_char().unwrap(), None);
+        io.write_str("hi ").unwrap();
+        io.write_byte(b'!').unwrap();
+        io.flush().unwrap();
+        assert_eq!(io.0, b"hi !");
+    }
+
+    #[test]
+    fn scripted() {
+        let mut out = Vec::new();
+        let mut io = Scripted::new(&mut out).line("12").line("ab");
+        assert_eq!(io.read_number().unwrap(), Some(12));
+        assert_eq!(io.read_char().unwrap(), Some('a'));
+        assert_eq!(io.read_char().unwrap(), Some('b'));
+        assert_eq!(io.read_char().unwrap(), Some('\n'));
+        assert_eq!(io.read_byte().unwrap(), None);
+        io.write_str("done\n").unwrap();
+        assert_eq!(out, b"done\n");
+
+        let mut out = Vec::new();
+        assert_eq!(Scripted::new(&mut out).read_char().unwrap(), None);
+    }
+}
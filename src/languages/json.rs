@@ -0,0 +1,464 @@
+use std::fmt;
+
+/// A JSON document. Objects keep their keys in insertion order so output is
+/// stable.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn object<K: Into<String>>(fields: Vec<(K, Value)>) -> Self {
+        Value::Object(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    /// Parses a complete JSON document. Errors give the byte offset they were
+    /// found at.
+    pub fn parse(text: &str) -> Result<Value, String> {
+        let mut parser = Parser {
+            text,
+            pos: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.pos < text.len() {
+            return Err(parser.error("end of input"));
+        }
+        Ok(value)
+    }
+
+    /// The value of `key`, if this is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+// How deeply arrays and objects may nest, so that a hostile document cannot
+// overflow the stack
+const MAX_DEPTH: usize = 512;
+
+// A recursive descent parser over the text of a document
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    /// Arrays and objects open around `pos`
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, expected: &str) -> String {
+        match self.text[self.pos..].chars().next() {
+            Some(c) => format!("at byte {}: expected {}, found `{}`", self.pos, expected, c),
+            None => format!(
+                "at byte {}: expected {}, found end of input",
+                self.pos, expected
+            ),
+        }
+    }
+
+    fn whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn eat(&mut self, literal: &str) -> bool {
+        if self.text[self.pos..].starts_with(literal) {
+            self.pos += literal.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.whitespace();
+        if self.eat("null") {
+            Ok(Value::Null)
+        } else if self.eat("true") {
+            Ok(Value::Bool(true))
+        } else if self.eat("false") {
+            Ok(Value::Bool(false))
+        } else if self.eat("[") {
+            self.nested(Self::array)
+        } else if self.eat("{") {
+            self.nested(Self::object)
+        } else if self.eat("\"") {
+            self.string().map(Value::String)
+        } else {
+            self.number()
+        }
+    }
+
+    // Parses the rest of an array or object with `rest`, one level deeper
+    fn nested(&mut self, rest: fn(&mut Self) -> Result<Value, String>) -> Result<Value, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!(
+                "at byte {}: arrays and objects nest deeper than {}",
+                self.pos - 1,
+                MAX_DEPTH
+            ));
+        }
+        self.depth += 1;
+        let value = rest(self);
+        self.depth -= 1;
+        value
+    }
+
+    // The rest of an array whose `[` has been eaten
+    fn array(&mut self) -> Result<Value, String> {
+        let mut items = Vec::new();
+        self.whitespace();
+        if self.eat("]") {
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.whitespace();
+            if self.eat("]") {
+                return Ok(Value::Array(items));
+            }
+            if !self.eat(",") {
+                return Err(self.error("`,` or `]`"));
+            }
+        }
+    }
+
+    // The rest of an object whose `{` has been eaten
+    fn object(&mut self) -> Result<Value, String> {
+        let mut fields = Vec::new();
+        self.whitespace();
+        if self.eat("}") {
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.whitespace();
+            if !self.eat("\"") {
+                return Err(self.error("a key"));
+            }
+            let key = self.string()?;
+            self.whitespace();
+            if !self.eat(":") {
+                return Err(self.error("`:`"));
+            }
+            fields.push((key, self.value()?));
+            self.whitespace();
+            if self.eat("}") {
+                return Ok(Value::Object(fields));
+            }
+            if !self.eat(",") {
+                return Err(self.error("`,` or `}`"));
+            }
+        }
+    }
+
+    // The rest of a string whose opening quote has been eaten
+    fn string(&mut self) -> Result<String, String> {
+        let mut s = String::new();
+        loop {
+            let Some(c) = self.text[self.pos..].chars().next() else {
+                return Err(self.error("`\"`"));
+            };
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escape = self.text[self.pos..].chars().next();
+                    self.pos += 1;
+                    match escape {
+                        Some('"') => s.push('"'),
+                        Some('\\') => s.push('\\'),
+                        Some('/') => s.push('/'),
+                        Some('b') => s.push('\u{8}'),
+                        Some('f') => s.push('\u{c}'),
+                        Some('n') => s.push('\n'),
+                        Some('r') => s.push('\r'),
+                        Some('t') => s.push('\t'),
+                        Some('u') => {
+                            let mut code = self.hex()?;
+                            // Characters outside the basic plane come as a
+                            // surrogate pair; half of one stands for nothing,
+                            // and the escape after it may start a pair itself
+                            while (0xd800..0xdc00).contains(&code) && self.eat("\\u") {
+                                let low = self.hex()?;
+                                if (0xdc00..0xe000).contains(&low) {
+                                    code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                                    break;
+                                }
+                                s.push(char::REPLACEMENT_CHARACTER);
+                                code = low;
+                            }
+                            s.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                        }
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error("an escape"));
+                        }
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.pos..self.pos + 4).unwrap_or_default();
+        if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(self.error("four hex digits"));
+        }
+        self.pos += 4;
+        Ok(u32::from_str_radix(digits, 16).expect("checked to be hex digits"))
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+            .unwrap_or(rest.len());
+        match rest[..len].parse() {
+            Ok(n) if len > 0 => {
+                self.pos += len;
+                Ok(Value::Number(n))
+            }
+            _ => Err(self.error("a value")),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Self {
+        Value::Number(n.into())
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(v: Vec<T>) -> Self {
+        Value::Array(v.into_iter().map(Into::into).collect())
+    }
+}
+
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+// Compact serialisation, suitable for one-document-per-line output
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) if !n.is_finite() => write!(f, "null"),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write_str(f, s),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_str(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scalars() {
+        assert_eq!(Value::Null.to_string(), "null");
+        assert_eq!(Value::from(true).to_string(), "true");
+        assert_eq!(Value::from(-12).to_string(), "-12");
+        assert_eq!(Value::Number(0.5).to_string(), "0.5");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            Value::from("a \"b\"\\\n\u{1}").to_string(),
+            "\"a \\\"b\\\"\\\\\\n\\u0001\""
+        );
+    }
+
+    #[test]
+    fn nested() {
+        let value = Value::object(vec![
+            ("code", Value::from("P002")),
+            ("related", Value::from(vec!["+", "4"])),
+            ("span", Value::Null),
+        ]);
+        assert_eq!(
+            value.to_string(),
+            r#"{"code":"P002","related":["+","4"],"span":null}"#
+        );
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let text = r#"{"id":1,"params":{"text":"a\n\"b\"","ok":[true,false,null,-2.5]}}"#;
+        let value = Value::parse(text).unwrap();
+        assert_eq!(value.to_string(), text);
+        assert_eq!(value.get("id").and_then(Value::as_f64), Some(1.0));
+        assert_eq!(
+            value
+                .get("params")
+                .and_then(|params| params.get("text"))
+                .and_then(Value::as_str),
+            Some("a\n\"b\"")
+        );
+    }
+
+    #[test]
+    fn parse_unicode_escapes() {
+        assert_eq!(
+            Value::parse(r#" "\u00e9\ud83d\ude00" "#),
+            Ok(Value::from("é😀"))
+        );
+    }
+
+    #[test]
+    fn parse_lone_surrogates() {
+        assert_eq!(
+            Value::parse(r#""\ud800\u0041""#),
+            Ok(Value::from("\u{fffd}A"))
+        );
+        assert_eq!(
+            Value::parse(r#""\ud800\ud800\ude00x""#),
+            Ok(Value::from("\u{fffd}\u{10200}x"))
+        );
+        assert_eq!(
+            Value::parse(r#""\ud800\ud800\udc00x""#),
+            Ok(Value::from("\u{fffd}\u{10000}x"))
+        );
+        assert_eq!(
+            Value::parse(r#""\ud800\ud800\ud800""#),
+            Ok(Value::from("\u{fffd}\u{fffd}\u{fffd}"))
+        );
+        assert_eq!(Value::parse(r#""\ude00""#), Ok(Value::from("\u{fffd}")));
+        assert_eq!(
+            Value::parse(r#""\u+123""#),
+            Err(String::from(
+                "at byte 3: expected four hex digits, found `+`"
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_nesting_limit() {
+        let deepest = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(Value::parse(&deepest).is_ok());
+        let deeper = format!("{}{}", "[{\"a\":".repeat(MAX_DEPTH / 2), "[1]");
+        assert_eq!(
+            Value::parse(&deeper),
+            Err(format!(
+                "at byte {}: arrays and objects nest deeper than 512",
+                deeper.len() - 3
+            ))
+        );
+        assert!(Value::parse(&"[".repeat(500_000)).is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            Value::parse("[1, 2"),
+            Err(String::from(
+                "at byte 5: expected `,` or `]`, found end of input"
+            ))
+        );
+        assert_eq!(
+            Value::parse("{\"a\" 1}"),
+            Err(String::from("at byte 5: expected `:`, found `1`"))
+        );
+        assert_eq!(
+            Value::parse("1 2"),
+            Err(String::from("at byte 2: expected end of input, found `2`"))
+        );
+    }
+}
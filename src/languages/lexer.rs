@@ -0,0 +1,237 @@
+use super::error::lexer::{Error, Result};
+use super::span::{Span, Spanned};
+use regex::Regex;
+use std::error::Error as StdError;
+use std::fmt;
+
+/// Declares a lexer as an ordered table of rules. Where several rules match
+/// at the same position the earliest one wins, as with alternation in a
+/// regular expression, so e.g. a keyword goes before a rule for identifiers.
+pub struct Builder<T> {
+    rules: Vec<(String, Rule<T>)>,
+}
+
+enum Rule<T> {
+    /// Turns the matched text into a token, or `None` when it cannot be one,
+    /// e.g. a number too large for its type
+    Token(fn(&str) -> Option<T>),
+    Skip,
+}
+
+impl<T> Default for Builder<T> {
+    fn default() -> Self {
+        Builder { rules: Vec::new() }
+    }
+}
+
+impl<T> Builder<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rule making a token of text matching `pattern`.
+    pub fn token(mut self, pattern: &str, make: fn(&str) -> Option<T>) -> Self {
+        self.rules.push((pattern.to_string(), Rule::Token(make)));
+        self
+    }
+
+    /// Adds a rule for text to pass over, such as whitespace or comments.
+    pub fn skip(mut self, pattern: &str) -> Self {
+        self.rules.push((pattern.to_string(), Rule::Skip));
+        self
+    }
+
+    /// Compiles every rule, failing if any pattern is invalid or matches the
+    /// empty string, which would keep every later rule from being tried.
+    pub fn build(self) -> std::result::Result<Lexer<T>, BuildError> {
+        let rules = self
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(i, (pattern, rule))| {
+                let regex = Regex::new(&format!(r"\A(?:{})", pattern)).map_err(|source| {
+                    BuildError::Pattern {
+                        rule: i + 1,
+                        source,
+                    }
+                })?;
+                if regex.is_match("") {
+                    return Err(BuildError::MatchesEmpty { rule: i + 1 });
+                }
+                Ok((regex, rule))
+            })
+            .collect::<std::result::Result<_, _>>()?;
+        Ok(Lexer { rules })
+    }
+}
+
+/// Why a [`Builder`]'s rules could not be compiled. Rules are numbered from 1,
+/// in the order they were added.
+#[derive(Debug)]
+pub enum BuildError {
+    /// The rule's pattern is not a valid regular expression
+    Pattern { rule: usize, source: regex::Error },
+    /// The rule's pattern matches the empty string
+    MatchesEmpty { rule: usize },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::Pattern { rule, .. } => write!(f, "rule {}: invalid pattern", rule),
+            BuildError::MatchesEmpty { rule } => {
+                write!(f, "rule {}: matches the empty string", rule)
+            }
+        }
+    }
+}
+
+impl StdError for BuildError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            BuildError::Pattern { source, .. } => Some(source),
+            BuildError::MatchesEmpty { .. } => None,
+        }
+    }
+}
+
+/// A compiled [`Builder`], ready to be used on any number of inputs.
+pub struct Lexer<T> {
+    /// Each rule with its pattern, anchored to where lexing has got to
+    rules: Vec<(Regex, Rule<T>)>,
+}
+
+impl<T> Lexer<T> {
+    /// Splits `input` from byte `pos` onwards into tokens, with spans into the
+    /// whole of `input`. Text no rule matches is reported a character at a
+    /// time; so is text only matched as the empty string, as `\b` can be.
+    pub fn lex(&self, input: &str, pos: usize) -> Result<Vec<Spanned<T>>, String> {
+        match self.lex_partial(input, pos) {
+            (tokens, None) => Ok(tokens),
+            (_, Some(e)) => Err(e),
+        }
+    }
+
+    /// Like [`lex`](Self::lex), but on failure also hands back the tokens
+    /// before the error, for callers that carry on past it.
+    pub fn lex_partial(
+        &self,
+        input: &str,
+        mut pos: usize,
+    ) -> (Vec<Spanned<T>>, Option<Error<String>>) {
+        let mut tokens = Vec::new();
+        while pos < input.len() {
+            // The earliest rule to match wins, as with alternation
+            let matched = self
+                .rules
+                .iter()
+                .enumerate()
+                .find_map(|(rule, (regex, _))| Some((rule, regex.find(&input[pos..])?.end())));
+            let (rule, len) = match matched {
+                Some((rule, len)) if len > 0 => (rule, len),
+                _ => return (tokens, Some(invalid_input(input, pos))),
+            };
+            let (start, end) = (pos, pos + len);
+            pos = end;
+            if let (_, Rule::Token(make)) = &self.rules[rule] {
+                let text = &input[start..end];
+                let span = Span::new(input, start, end);
+                match make(text) {
+                    Some(tok) => tokens.push(Spanned::new(tok, span)),
+                    None => {
+                        let error = Error::invalid_input(text.to_string()).with_span(span);
+                        return (tokens, Some(error));
+                    }
+                }
+            }
+        }
+        (tokens, None)
+    }
+}
+
+// Reports the single character at `pos` as the offending input
+fn invalid_input(input: &str, pos: usize) -> Error<String> {
+    let c = input[pos..].chars().next().unwrap_or_default();
+    Error::invalid_input(c.to_string()).with_span(Span::new(input, pos, pos + c.len_utf8()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(PartialEq, Debug)]
+    enum Tok {
+        Let,
+        Ident(String),
+        Byte(u8),
+    }
+
+    fn lexer() -> Lexer<Tok> {
+        Builder::new()
+            .skip(r"\s+")
+            .token(r"let\b", |_| Some(Tok::Let))
+            .token(r"[a-z]+", |text| Some(Tok::Ident(text.to_string())))
+            .token(r"[0-9]+", |text| text.parse().ok().map(Tok::Byte))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn earlier_rules_win() {
+        let tokens = lexer().lex("let letter 12", 0).unwrap();
+        assert_eq!(
+            tokens,
+            vec![Tok::Let, Tok::Ident(String::from("letter")), Tok::Byte(12)]
+        );
+        assert_eq!(tokens[1].span, Span::new("let letter 12", 4, 10));
+    }
+
+    #[test]
+    fn from_position() {
+        let tokens = lexer().lex("x\ny z", 2).unwrap();
+        assert_eq!(tokens[1].span.line, 2);
+        assert_eq!(tokens[1].span.col, 3);
+    }
+
+    #[test]
+    fn errors() {
+        let err = lexer().lex("let é", 0).unwrap_err();
+        assert_eq!(err.to_string(), "unrecognised input `é`");
+        assert_eq!(err.span(), Some(Span::new("let é", 4, 6)));
+
+        // A match the rule cannot make a token of is reported whole
+        let err = lexer().lex("1 256", 0).unwrap_err();
+        assert_eq!(err.to_string(), "unrecognised input `256`");
+        assert_eq!(err.span(), Some(Span::new("1 256", 2, 5)));
+
+        let (tokens, err) = lexer().lex_partial("a 1 ! b", 0);
+        assert_eq!(tokens, vec![Tok::Ident(String::from("a")), Tok::Byte(1)]);
+        assert_eq!(err.and_then(|e| e.span()), Some(Span::new("a 1 ! b", 4, 5)));
+    }
+
+    #[test]
+    fn empty_matches() {
+        let built = Builder::new()
+            .token("a*", |_| Some(()))
+            .token("b", |_| Some(()))
+            .build();
+        let err = built.err().unwrap();
+        assert!(matches!(err, BuildError::MatchesEmpty { rule: 1 }));
+        assert_eq!(err.to_string(), "rule 1: matches the empty string");
+        assert!(err.source().is_none());
+        // Patterns that only match nothing in context get past the builder
+        let lexer = Builder::new().token(r"\b", |_| Some(())).build().unwrap();
+        assert_eq!(
+            lexer.lex("a", 0).unwrap_err().span().map(|s| s.start),
+            Some(0)
+        );
+        let err = Builder::<()>::new()
+            .skip(" ")
+            .token("(", |_| None)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, BuildError::Pattern { rule: 2, .. }));
+        assert!(err.source().is_some());
+    }
+}
@@ -1,2 +1,9 @@
 pub mod calculator;
+pub mod combinator;
+pub mod diagnostic;
 pub mod error;
+pub mod grammar;
+pub mod io;
+pub mod json;
+pub mod lexer;
+pub mod span;
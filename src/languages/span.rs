@@ -0,0 +1,166 @@
+use super::json::Value;
+use std::fmt;
+
+/// A region of source text: byte offsets `start..end` plus the 1-based line and
+/// column of `start`. Columns count bytes, as offsets do, so that they can be
+/// worked out from a span alone; tools wanting another unit, such as UTF-16 for
+/// editors, convert from the offsets.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    pub fn new(input: &str, start: usize, end: usize) -> Self {
+        let before = &input[..start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let col = start - line_start + 1;
+        Span {
+            start,
+            end,
+            line,
+            col,
+        }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        if other.start < self.start {
+            other.to(self)
+        } else {
+            Span {
+                end: self.end.max(other.end),
+                ..self
+            }
+        }
+    }
+
+    /// The empty span just past the end of `self`. Only exact for spans that do
+    /// not cross a line break.
+    pub fn after(self) -> Span {
+        Span {
+            start: self.end,
+            end: self.end,
+            line: self.line,
+            col: self.col + (self.end - self.start),
+        }
+    }
+
+    /// Renders the source line containing the span with a `^~~` underline beneath
+    /// it, e.g.
+    ///
+    /// ```text
+    /// + 5 ^ 3
+    ///     ^
+    /// ```
+    pub fn underline(&self, input: &str) -> String {
+        let start = self.start.min(input.len());
+        let line_start = input[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = input[start..].find('\n').map_or(input.len(), |i| start + i);
+        let line = &input[line_start..line_end];
+
+        let mut marker: String = input[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = input[start..self.end.clamp(start, line_end)]
+            .chars()
+            .count();
+        marker.push('^');
+        marker.push_str(&"~".repeat(width.saturating_sub(1)));
+
+        format!("{}\n{}", line, marker)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+impl From<Span> for Value {
+    fn from(span: Span) -> Self {
+        Value::object(vec![
+            ("start", Value::from(span.start)),
+            ("end", Value::from(span.end)),
+            ("line", Value::from(span.line)),
+            ("col", Value::from(span.col)),
+        ])
+    }
+}
+
+/// A value tagged with the span of source text it was produced from.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Spanned { node, span }
+    }
+}
+
+// Lets a spanned value be compared against a bare one when the location does not
+// matter, e.g. `lexer(..) == vec![Tok::TokAdd]`.
+impl<T: PartialEq> PartialEq<T> for Spanned<T> {
+    fn eq(&self, other: &T) -> bool {
+        self.node == *other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_and_column() {
+        let input = "+ 1 2\n* 3 4";
+        assert_eq!(
+            Span::new(input, 8, 9),
+            Span {
+                start: 8,
+                end: 9,
+                line: 2,
+                col: 3
+            }
+        );
+    }
+
+    #[test]
+    fn byte_columns() {
+        let input = "é x\n+ é";
+        assert_eq!(Span::new(input, 3, 4).col, 4);
+        // Past a token with multi-byte characters in it
+        let token = Span::new(input, 7, 9);
+        assert_eq!(token.after(), Span::new(input, 9, 9));
+        assert_eq!(token.after().col, 5);
+        assert_eq!(token.after().underline(input), "+ é\n   ^");
+    }
+
+    #[test]
+    fn underline_single() {
+        let input = "+ 5 ^ 3";
+        assert_eq!(Span::new(input, 4, 5).underline(input), "+ 5 ^ 3\n    ^");
+    }
+
+    #[test]
+    fn underline_range() {
+        let input = "1 2\n/ 10 0";
+        assert_eq!(Span::new(input, 4, 10).underline(input), "/ 10 0\n^~~~~~");
+    }
+
+    #[test]
+    fn underline_end_of_input() {
+        let input = "+ 5";
+        let span = Span::new(input, 2, 3).after();
+        assert_eq!(span.col, 4);
+        assert_eq!(span.underline(input), "+ 5\n   ^");
+    }
+}
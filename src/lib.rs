@@ -1 +1,5 @@
+pub mod dap;
+pub mod etop;
+mod framing;
 pub mod languages;
+pub mod lsp;
@@ -0,0 +1,127 @@
+use super::Language;
+use crate::languages::calculator::{
+    interpreter, lexer, parser, pretty, CalculatorError, Expr, Node, Stmt,
+};
+use crate::languages::diagnostic::Diagnostic;
+use crate::languages::span::Span;
+
+/// Editor support for the calculator language.
+pub struct Calculator;
+
+impl Language for Calculator {
+    fn id(&self) -> &'static str {
+        "calculator"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["calc"]
+    }
+
+    fn diagnostics(&self, text: &str) -> Vec<Diagnostic> {
+        let toks = match lexer::lexer(text, 0) {
+            Ok(toks) => toks,
+            Err(e) => return vec![Diagnostic::from(&CalculatorError::Lexer(e))],
+        };
+        parser::program_with_recovery(toks)
+            .errors
+            .into_iter()
+            .map(|e| Diagnostic::from(&CalculatorError::Parser(e)))
+            .collect()
+    }
+
+    fn hover(&self, text: &str, offset: usize) -> Option<(Span, String)> {
+        let program = parser::program_with_recovery(lexer::lexer(text, 0).ok()?).ast;
+        let node = program.iter().find_map(|stmt| {
+            let (Stmt::Expr(e) | Stmt::Print(e)) = &stmt.node;
+            innermost(e, offset)
+        })?;
+        let value = match interpreter::interpreter(node.clone()) {
+            Ok(value) => value.to_string(),
+            Err(e) => format!("{} ({})", e, e.code()),
+        };
+        Some((node.span, format!("`{}` = {}", pretty::expr(node), value)))
+    }
+
+    fn format(&self, text: &str) -> Option<Vec<(Span, String)>> {
+        let program = parser::program(lexer::lexer(text, 0).ok()?).ok()?;
+        // Only statements are rewritten, so comments and blank lines between
+        // them stay as they are
+        Some(
+            program
+                .iter()
+                .map(|stmt| (stmt.span, pretty::statement(stmt)))
+                .filter(|(span, formatted)| text[span.start..span.end] != *formatted)
+                .collect(),
+        )
+    }
+}
+
+// The smallest expression whose span contains `offset`
+fn innermost(node: &Node, offset: usize) -> Option<&Node> {
+    if !(node.span.start <= offset && offset < node.span.end) {
+        return None;
+    }
+    let children = match &node.node {
+        Expr::Add((e1, e2)) | Expr::Sub((e1, e2)) | Expr::Mult((e1, e2)) | Expr::Div((e1, e2)) => {
+            vec![e1, e2]
+        }
+        Expr::Int(_) | Expr::Error => vec![],
+    };
+    children
+        .into_iter()
+        .find_map(|child| innermost(child, offset))
+        .or(Some(node))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages::diagnostic::Phase;
+
+    #[test]
+    fn parser_diagnostics() {
+        let diagnostics = Calculator.diagnostics("+ 1\n* 2 3\n/ 4");
+        let codes: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.code, d.span.map(|s| s.line)))
+            .collect();
+        assert_eq!(codes, vec![("P003", Some(1)), ("P003", Some(3))]);
+        assert_eq!(diagnostics[0].phase, Phase::Parser);
+    }
+
+    #[test]
+    fn lexer_diagnostic() {
+        let diagnostics = Calculator.diagnostics("+ 1 2\n% 3");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "L001");
+        assert_eq!(diagnostics[0].span, Some(Span::new("+ 1 2\n% 3", 6, 7)));
+    }
+
+    #[test]
+    fn hover_innermost() {
+        let text = "print + 5 * 4 3";
+        let (span, value) = Calculator.hover(text, 10).unwrap();
+        assert_eq!(&text[span.start..span.end], "* 4 3");
+        assert_eq!(value, "`* 4 3` = 12");
+        assert_eq!(Calculator.hover(text, 6).unwrap().1, "`+ 5 * 4 3` = 17");
+        assert_eq!(Calculator.hover(text, 2), None);
+    }
+
+    #[test]
+    fn hover_error() {
+        let (_, value) = Calculator.hover("+ 1 / 2 0", 4).unwrap();
+        assert_eq!(value, "`/ 2 0` = division by zero (I001)");
+    }
+
+    #[test]
+    fn format_keeps_comments() {
+        let text = "# setup\nprint  +   1 2 ;; sum\n\n* 3 4; -  5 6\n";
+        let edits = Calculator.format(text).unwrap();
+        let mut formatted = text.to_string();
+        for (span, replacement) in edits.iter().rev() {
+            formatted.replace_range(span.start..span.end, replacement);
+        }
+        assert_eq!(formatted, "# setup\nprint + 1 2 ;; sum\n\n* 3 4; - 5 6\n");
+        assert_eq!(Calculator.format("+ 1"), None);
+    }
+}
@@ -0,0 +1,467 @@
+use crate::framing;
+use crate::languages::diagnostic::Diagnostic;
+use crate::languages::json::Value;
+use crate::languages::span::Span;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+mod calculator;
+
+pub use calculator::Calculator;
+
+/// What the server needs from a language to support editing it.
+pub trait Language {
+    /// The `languageId` editors open its documents with
+    fn id(&self) -> &'static str;
+
+    /// Extensions of its files, without the dot, for documents opened under
+    /// another id
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Every problem that can be found in `text` without running it.
+    fn diagnostics(&self, text: &str) -> Vec<Diagnostic>;
+
+    /// The innermost expression around byte `offset`, with its value described.
+    fn hover(&self, text: &str, offset: usize) -> Option<(Span, String)>;
+
+    /// Replacements for the parts of `text` not laid out canonically, or `None`
+    /// if it does not parse.
+    fn format(&self, text: &str) -> Option<Vec<(Span, String)>>;
+}
+
+/// Every language the crate implements.
+pub fn languages() -> Vec<Box<dyn Language>> {
+    vec![Box::new(Calculator)]
+}
+
+/// Speaks the Language Server Protocol over `input` and `output` until the
+/// client says `exit`, returning whether it asked for a `shutdown` first.
+pub fn serve(
+    input: impl BufRead,
+    output: impl Write,
+    languages: Vec<Box<dyn Language>>,
+) -> io::Result<bool> {
+    let mut server = Server {
+        input,
+        output,
+        languages,
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+    server.run()
+}
+
+// An open document and the index of its language
+struct Document {
+    text: String,
+    language: usize,
+}
+
+struct Server<R, W> {
+    input: R,
+    output: W,
+    languages: Vec<Box<dyn Language>>,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+// JSON-RPC error codes
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+impl<R: BufRead, W: Write> Server<R, W> {
+    fn run(&mut self) -> io::Result<bool> {
+        while let Some(body) = framing::receive(&mut self.input)? {
+            let message = match Value::parse(&body) {
+                Ok(message) => message,
+                Err(e) => {
+                    self.respond(Value::Null, Err((PARSE_ERROR, e)))?;
+                    continue;
+                }
+            };
+            let id = message.get("id").cloned();
+            let params = message.get("params").cloned().unwrap_or(Value::Null);
+            let Some(method) = message.get("method").and_then(Value::as_str) else {
+                // A response to a request of ours; we make none
+                if id.is_none() {
+                    self.respond(
+                        Value::Null,
+                        Err((INVALID_REQUEST, String::from("no method"))),
+                    )?;
+                }
+                continue;
+            };
+            if method == "exit" {
+                return Ok(self.shutdown);
+            }
+            match id {
+                Some(id) => {
+                    let result = self.request(method, &params);
+                    self.respond(id, result)?;
+                }
+                None => self.notification(method, &params)?,
+            }
+        }
+        Ok(false)
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i32, String)> {
+        match method {
+            "initialize" => Ok(Value::object(vec![
+                (
+                    "capabilities",
+                    Value::object(vec![
+                        // Whole documents are sent on every change
+                        ("textDocumentSync", Value::from(1)),
+                        ("hoverProvider", Value::from(true)),
+                        ("documentFormattingProvider", Value::from(true)),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    Value::object(vec![
+                        ("name", Value::from("languages")),
+                        ("version", Value::from(env!("CARGO_PKG_VERSION"))),
+                    ]),
+                ),
+            ])),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => {
+                let (document, offset) = self.position(params)?;
+                let Some(document) = document else {
+                    return Ok(Value::Null);
+                };
+                let language = &self.languages[document.language];
+                Ok(match language.hover(&document.text, offset) {
+                    Some((span, value)) => Value::object(vec![
+                        (
+                            "contents",
+                            Value::object(vec![
+                                ("kind", Value::from("markdown")),
+                                ("value", Value::from(value)),
+                            ]),
+                        ),
+                        ("range", range(&document.text, Some(span))),
+                    ]),
+                    None => Value::Null,
+                })
+            }
+            "textDocument/formatting" => {
+                let Some(document) = self.documents.get(uri(params)?) else {
+                    return Ok(Value::Null);
+                };
+                let language = &self.languages[document.language];
+                let edits = language.format(&document.text).map(|edits| {
+                    edits
+                        .into_iter()
+                        .map(|(span, text)| {
+                            Value::object(vec![
+                                ("range", range(&document.text, Some(span))),
+                                ("newText", Value::from(text)),
+                            ])
+                        })
+                        .collect::<Vec<_>>()
+                });
+                Ok(Value::from(edits))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        let document = params.get("textDocument");
+        let uri = document.and_then(|d| d.get("uri")).and_then(Value::as_str);
+        let Some(uri) = uri else {
+            return Ok(());
+        };
+        match method {
+            "textDocument/didOpen" => {
+                let id = document
+                    .and_then(|d| d.get("languageId"))
+                    .and_then(Value::as_str);
+                let text = document.and_then(|d| d.get("text")).and_then(Value::as_str);
+                if let (Some(language), Some(text)) = (self.language(id, uri), text) {
+                    let text = text.to_string();
+                    self.documents
+                        .insert(uri.to_string(), Document { text, language });
+                    self.publish(uri)?;
+                }
+            }
+            "textDocument/didChange" => {
+                // With full sync the last change holds the whole text
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Value::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Value::as_str);
+                if let (Some(document), Some(text)) = (self.documents.get_mut(uri), text) {
+                    document.text = text.to_string();
+                    self.publish(uri)?;
+                }
+            }
+            "textDocument/didClose" if self.documents.remove(uri).is_some() => {
+                self.notify(
+                    "textDocument/publishDiagnostics",
+                    Value::object(vec![
+                        ("uri", Value::from(uri)),
+                        ("diagnostics", Value::Array(vec![])),
+                    ]),
+                )?;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    // The language a document is in, by its id or else its file extension
+    fn language(&self, id: Option<&str>, uri: &str) -> Option<usize> {
+        let extension = uri.rsplit_once('.').map(|(_, extension)| extension);
+        self.languages
+            .iter()
+            .position(|language| Some(language.id()) == id)
+            .or_else(|| {
+                self.languages.iter().position(|language| {
+                    extension.is_some_and(|e| language.extensions().contains(&e))
+                })
+            })
+    }
+
+    // The document and byte offset a `TextDocumentPositionParams` points at
+    fn position(&self, params: &Value) -> Result<(Option<&Document>, usize), (i32, String)> {
+        let Some(document) = self.documents.get(uri(params)?) else {
+            return Ok((None, 0));
+        };
+        let position = params.get("position");
+        let line = position.and_then(|p| p.get("line")).and_then(Value::as_f64);
+        let character = position
+            .and_then(|p| p.get("character"))
+            .and_then(Value::as_f64);
+        match (line, character) {
+            (Some(line), Some(character)) => Ok((
+                Some(document),
+                offset(&document.text, line as usize, character as usize),
+            )),
+            _ => Err((INVALID_PARAMS, String::from("expected a position"))),
+        }
+    }
+
+    fn publish(&mut self, uri: &str) -> io::Result<()> {
+        let document = &self.documents[uri];
+        let language = &self.languages[document.language];
+        let diagnostics: Vec<Value> = language
+            .diagnostics(&document.text)
+            .into_iter()
+            .map(|diagnostic| {
+                // Editors show the message alone, so it carries the notes and help
+                let mut message = diagnostic.message;
+                for note in &diagnostic.notes {
+                    message.push_str(&format!("\nnote: {}", note));
+                }
+                if let Some(help) = &diagnostic.help {
+                    message.push_str(&format!("\nhelp: {}", help));
+                }
+                Value::object(vec![
+                    ("range", range(&document.text, diagnostic.span)),
+                    // Everything found so far stops the program running
+                    ("severity", Value::from(1)),
+                    ("code", Value::from(diagnostic.code)),
+                    (
+                        "source",
+                        Value::from(format!("{} {}", language.id(), diagnostic.phase.as_str())),
+                    ),
+                    ("message", Value::from(message)),
+                ])
+            })
+            .collect();
+        self.notify(
+            "textDocument/publishDiagnostics",
+            Value::object(vec![
+                ("uri", Value::from(uri)),
+                ("diagnostics", Value::Array(diagnostics)),
+            ]),
+        )
+    }
+
+    fn send(&mut self, message: Value) -> io::Result<()> {
+        framing::send(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, id: Value, result: Result<Value, (i32, String)>) -> io::Result<()> {
+        let outcome = match result {
+            Ok(result) => ("result", result),
+            Err((code, message)) => (
+                "error",
+                Value::object(vec![
+                    ("code", Value::from(code)),
+                    ("message", Value::from(message)),
+                ]),
+            ),
+        };
+        self.send(Value::object(vec![
+            ("jsonrpc", Value::from("2.0")),
+            ("id", id),
+            outcome,
+        ]))
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        self.send(Value::object(vec![
+            ("jsonrpc", Value::from("2.0")),
+            ("method", Value::from(method)),
+            ("params", params),
+        ]))
+    }
+}
+
+fn uri(params: &Value) -> Result<&str, (i32, String)> {
+    params
+        .get("textDocument")
+        .and_then(|d| d.get("uri"))
+        .and_then(Value::as_str)
+        .ok_or_else(|| (INVALID_PARAMS, String::from("expected a text document")))
+}
+
+// LSP positions count lines from 0 and characters in UTF-16 code units
+fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Value::object(vec![
+        ("line", Value::from(before.matches('\n').count())),
+        (
+            "character",
+            Value::from(before[line_start..].encode_utf16().count()),
+        ),
+    ])
+}
+
+fn offset(text: &str, line: usize, character: usize) -> usize {
+    let line_start = match line {
+        0 => 0,
+        _ => text
+            .match_indices('\n')
+            .nth(line - 1)
+            .map_or(text.len(), |(i, _)| i + 1),
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn range(text: &str, span: Option<Span>) -> Value {
+    let span = span.unwrap_or_default();
+    Value::object(vec![
+        ("start", position(text, span.start)),
+        ("end", position(text, span.end)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(messages: &[&str]) -> Vec<u8> {
+        messages
+            .iter()
+            .flat_map(|body| format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes())
+            .collect()
+    }
+
+    // Runs a session and returns every message the server sent
+    fn session(messages: &[&str]) -> (bool, Vec<Value>) {
+        let mut out = Vec::new();
+        let clean = serve(&frame(messages)[..], &mut out, languages()).unwrap();
+        let mut replies = Vec::new();
+        let mut rest = &out[..];
+        while let Some(body) = framing::receive(&mut rest).unwrap() {
+            replies.push(Value::parse(&body).unwrap());
+        }
+        (clean, replies)
+    }
+
+    const OPEN: &str = r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.calc","languageId":"calculator","version":1,"text":"+ 1\n* é 2"}}}"#;
+
+    #[test]
+    fn lifecycle() {
+        let (clean, replies) = session(&[
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/rename","params":{}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        ]);
+        assert!(clean);
+        assert_eq!(
+            replies[0]
+                .get("result")
+                .unwrap()
+                .get("capabilities")
+                .unwrap()
+                .to_string(),
+            r#"{"textDocumentSync":1,"hoverProvider":true,"documentFormattingProvider":true}"#
+        );
+        assert_eq!(
+            replies[1].get("error").unwrap().get("code"),
+            Some(&Value::from(METHOD_NOT_FOUND))
+        );
+        assert_eq!(replies[2].get("result"), Some(&Value::Null));
+        assert!(!session(&[r#"{"jsonrpc":"2.0","method":"exit"}"#]).0);
+    }
+
+    #[test]
+    fn publishes_diagnostics() {
+        let (_, replies) = session(&[
+            OPEN,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///a.calc","version":2},"contentChanges":[{"text":"+ 1 2"}]}}"#,
+        ]);
+        let diagnostics = replies[0]
+            .get("params")
+            .unwrap()
+            .get("diagnostics")
+            .unwrap();
+        assert_eq!(
+            diagnostics.to_string(),
+            r#"[{"range":{"start":{"line":1,"character":2},"end":{"line":1,"character":3}},"severity":1,"code":"L001","source":"calculator lexer","message":"unrecognised input `é`"}]"#
+        );
+        assert_eq!(
+            replies[1].get("params").unwrap().get("diagnostics"),
+            Some(&Value::Array(vec![]))
+        );
+    }
+
+    #[test]
+    fn hover_and_format() {
+        let (_, replies) = session(&[
+            r##"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///b.calc","languageId":"plaintext","version":1,"text":"# sum\nprint  + 1   * 2 3"}}}"##,
+            r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///b.calc"},"position":{"line":1,"character":14}}}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/formatting","params":{"textDocument":{"uri":"file:///b.calc"},"options":{"tabSize":4,"insertSpaces":true}}}"#,
+        ]);
+        assert_eq!(
+            replies[1].get("result").unwrap().to_string(),
+            r#"{"contents":{"kind":"markdown","value":"`* 2 3` = 6"},"range":{"start":{"line":1,"character":13},"end":{"line":1,"character":18}}}"#
+        );
+        assert_eq!(
+            replies[2].get("result").unwrap().to_string(),
+            r#"[{"range":{"start":{"line":1,"character":0},"end":{"line":1,"character":18}},"newText":"print + 1 * 2 3"}]"#
+        );
+    }
+
+    #[test]
+    fn utf16_positions() {
+        let text = "é😀x\ny";
+        assert_eq!(position(text, 6).to_string(), r#"{"line":0,"character":3}"#);
+        assert_eq!(offset(text, 0, 3), 6);
+        assert_eq!(offset(text, 1, 0), 8);
+        assert_eq!(offset(text, 0, 99), 7);
+    }
+}
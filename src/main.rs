@@ -1,13 +1,188 @@
-use crate::etop::etop;
+use languages::etop::{etop, server, ErrorFormat};
+use languages::languages::calculator::{lexer, parser, pretty, CalculatorError};
+use languages::languages::diagnostic::Diagnostic;
+use languages::{dap, lsp};
 
-mod etop;
+struct Args {
+    language: Option<String>,
+    error_format: ErrorFormat,
+    record: Option<String>,
+    listen: Option<String>,
+    /// A calculator program to draw as a DOT graph instead of starting etop
+    dot: Option<String>,
+    dot_values: bool,
+}
+
+// Flags that take a value, as `--flag=value` or `--flag value`
+const VALUED: [&str; 4] = ["--error-format", "--record", "--listen", "--dot"];
+
+fn parse_args(argv: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut args = Args {
+        language: None,
+        error_format: ErrorFormat::Human,
+        record: None,
+        listen: None,
+        dot: None,
+        dot_values: false,
+    };
+    let mut argv = argv.into_iter();
+    while let Some(arg) = argv.next() {
+        if !arg.starts_with('-') && args.language.is_none() {
+            args.language = Some(arg);
+            continue;
+        }
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None if VALUED.contains(&arg.as_str()) => {
+                let value = argv
+                    .next()
+                    .ok_or_else(|| format!("`{}` needs a value", arg))?;
+                (arg.as_str(), Some(value))
+            }
+            None => (arg.as_str(), None),
+        };
+        match (flag, value.as_deref()) {
+            ("--error-format", Some("human")) => args.error_format = ErrorFormat::Human,
+            ("--error-format", Some("json")) => args.error_format = ErrorFormat::Json,
+            ("--error-format", Some(format)) => {
+                return Err(format!("unknown error format `{}`", format))
+            }
+            ("--record", Some(path)) => args.record = Some(path.to_string()),
+            ("--listen", Some(addr)) => args.listen = Some(addr.to_string()),
+            ("--dot", Some(path)) => args.dot = Some(path.to_string()),
+            ("--dot-values", None) => args.dot_values = true,
+            _ => return Err(format!("unexpected argument `{}`", arg)),
+        }
+    }
+    if args.listen.is_some() && args.record.is_some() {
+        return Err(String::from("`--record` cannot be used with `--listen`"));
+    }
+    if args.dot.is_some() && (args.listen.is_some() || args.record.is_some()) {
+        return Err(String::from("`--dot` starts no session to record or serve"));
+    }
+    if args.dot_values && args.dot.is_none() {
+        return Err(String::from("`--dot-values` needs `--dot=<program>`"));
+    }
+    if args.dot.is_some()
+        && args
+            .language
+            .as_ref()
+            .is_some_and(|language| !language.eq_ignore_ascii_case("calculator"))
+    {
+        return Err(String::from("`--dot` only draws calculator programs"));
+    }
+    Ok(args)
+}
+
+// The syntax trees of the calculator program at `path`, as a DOT graph, or
+// what to report instead, in `error_format`
+fn dot(path: &str, values: bool, error_format: ErrorFormat) -> Result<String, String> {
+    let src = std::fs::read_to_string(path)
+        .map_err(|e| format!("error: could not read `{}`: {}\n", path, e))?;
+    let program = lexer::lexer(&src, 0)
+        .map_err(CalculatorError::Lexer)
+        .and_then(|toks| parser::program(toks).map_err(CalculatorError::Parser))
+        .map_err(|e| {
+            let diagnostic = Diagnostic::from(&e);
+            match error_format {
+                ErrorFormat::Human => diagnostic.render(&src, Some(path), "error: "),
+                ErrorFormat::Json => format!("{}\n", diagnostic.to_json()),
+            }
+        })?;
+    Ok(pretty::dot(&program, values))
+}
 
-// struct Args {
-// language: String,
-// }
 fn main() {
-    // let language = std::env::args().nth(1).expect("no language given");
-    // let args = Args { language };
-    // etop(&args.language);
-    etop();
+    // `lsp` and `dap` are for editors, which start them with no other arguments
+    match std::env::args().nth(1).as_deref() {
+        Some("lsp") => {
+            let stdin = std::io::stdin();
+            match lsp::serve(stdin.lock(), std::io::stdout(), lsp::languages()) {
+                Ok(true) => std::process::exit(0),
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("dap") => {
+            let stdin = std::io::stdin();
+            if let Err(e) = dap::serve(stdin.lock(), std::io::stdout(), dap::debuggees()) {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => (),
+    }
+    let args = parse_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        eprintln!(
+            "usage: languages [--error-format=human|json] [--record=<transcript> | --listen=<addr>] [language]"
+        );
+        eprintln!("       languages --dot=<program> [--dot-values]");
+        eprintln!("       languages lsp | dap");
+        std::process::exit(2);
+    });
+    if let Some(path) = &args.dot {
+        match dot(path, args.dot_values, args.error_format) {
+            Ok(graph) => print!("{}", graph),
+            Err(report) => {
+                eprint!("{}", report);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let run = match &args.listen {
+        Some(addr) => server::listen(addr, args.language.as_deref(), args.error_format),
+        None => etop(
+            args.language.as_deref(),
+            args.error_format,
+            args.record.as_deref(),
+        ),
+    };
+    if let Err(e) = run {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(argv: &[&str]) -> Result<Args, String> {
+        parse_args(argv.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn flag_values() {
+        let args = parse(&["--listen", "127.0.0.1:7777", "--error-format", "json"]).unwrap();
+        assert_eq!(args.listen.as_deref(), Some("127.0.0.1:7777"));
+        assert_eq!(args.error_format, ErrorFormat::Json);
+        let args = parse(&["--listen=127.0.0.1:7777", "calculator"]).unwrap();
+        assert_eq!(args.listen.as_deref(), Some("127.0.0.1:7777"));
+        assert_eq!(args.language.as_deref(), Some("calculator"));
+        let args = parse(&["--dot", "a.calc", "--dot-values"]).unwrap();
+        assert_eq!(args.dot.as_deref(), Some("a.calc"));
+        assert!(args.dot_values);
+    }
+
+    #[test]
+    fn bad_flags() {
+        assert_eq!(
+            parse(&["--record"]).err().as_deref(),
+            Some("`--record` needs a value")
+        );
+        assert_eq!(
+            parse(&["--error-format", "xml"]).err().as_deref(),
+            Some("unknown error format `xml`")
+        );
+        assert_eq!(
+            parse(&["--dot-values=yes"]).err().as_deref(),
+            Some("unexpected argument `--dot-values=yes`")
+        );
+    }
 }